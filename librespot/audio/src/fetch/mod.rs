@@ -4,11 +4,12 @@ use std::{
     cmp::min,
     fs,
     io::{self, Read, Seek, SeekFrom},
+    num::NonZeroU32,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc, OnceLock,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use futures_util::{future::IntoStream, StreamExt, TryFutureExt};
@@ -19,7 +20,13 @@ use tempfile::NamedTempFile;
 use thiserror::Error;
 use tokio::sync::{mpsc, oneshot, Semaphore};
 
-use librespot_core::{cdn_url::CdnUrl, Error, FileId, Session};
+use librespot_core::{
+    cdn_url::CdnUrl,
+    config::RateLimitConfig,
+    http_client::{self, HttpClient},
+    rate_limit::RateLimits,
+    Error, FileId, Session,
+};
 
 use self::receive::audio_file_fetch;
 
@@ -27,6 +34,9 @@ use crate::range_set::{Range, RangeSet};
 
 pub type AudioFileResult = Result<(), librespot_core::Error>;
 
+// How often opening a file is tried when it fails with a transient error
+const MAX_OPEN_TRIES: usize = 3;
+
 #[derive(Error, Debug)]
 pub enum AudioFileError {
     #[error("other end of channel disconnected")]
@@ -43,6 +53,17 @@ pub enum AudioFileError {
     WaitTimeout,
 }
 
+// Rate limited and unavailable responses are reported the way the HTTP client does, so they
+// are retryable and tell how long to wait
+fn status_error(response: &Response<Incoming>) -> Error {
+    match response.status() {
+        code @ (StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE) => {
+            HttpClient::status_error(code, response.headers())
+        }
+        code => AudioFileError::StatusCode(code).into(),
+    }
+}
+
 impl From<AudioFileError> for Error {
     fn from(err: AudioFileError) -> Self {
         match err {
@@ -93,6 +114,15 @@ pub struct AudioFetchParams {
 
     /// The time we will wait to obtain status updates on downloading.
     pub download_timeout: Duration,
+
+    /// The most bytes per second received for all audio files together, or `None` to fetch
+    /// as fast as the connection allows.
+    pub max_bytes_per_second: Option<NonZeroU32>,
+
+    /// The number of requests that may be streaming parts of a file at the same time. Ranges
+    /// of more than one `minimum_download_size` are split into disjoint parts that are
+    /// fetched in parallel.
+    pub parallel_downloads: usize,
 }
 
 impl Default for AudioFetchParams {
@@ -110,6 +140,29 @@ impl Default for AudioFetchParams {
             download_timeout: Duration::from_secs(
                 (minimum_download_size / minimum_throughput) as u64,
             ),
+            max_bytes_per_second: None,
+            parallel_downloads: 1,
+        }
+    }
+}
+
+impl AudioFetchParams {
+    /// Parameters for fetching whole files as quickly as possible rather than just ahead of
+    /// the playback position. Requests are made in large blocks, with up to a minute of audio
+    /// read ahead, so fewer round trips are spent on each file.
+    pub fn bulk() -> Self {
+        let minimum_download_size = 1024 * 1024;
+        let minimum_throughput = 64 * 1024;
+        Self {
+            minimum_download_size,
+            minimum_throughput,
+            read_ahead_during_playback: Duration::from_secs(60),
+            prefetch_threshold_factor: 16.0,
+            parallel_downloads: 4,
+            download_timeout: Duration::from_secs(
+                (minimum_download_size / minimum_throughput) as u64,
+            ),
+            ..Default::default()
         }
     }
 }
@@ -126,6 +179,30 @@ impl AudioFetchParams {
     }
 }
 
+// Shared by all audio files, so that `max_bytes_per_second` holds for them together
+static THROTTLE: OnceLock<Option<RateLimits>> = OnceLock::new();
+
+// Waits until receiving `bytes` more stays within both the byte rate limit of the session's
+// HTTP client and `AudioFetchParams::max_bytes_per_second`, and returns how long that took
+async fn throttle(session: &Session, bytes: usize) -> Duration {
+    let start = Instant::now();
+    session.http_client().rate_limits().received(bytes).await;
+
+    let throttle = THROTTLE.get_or_init(|| {
+        AudioFetchParams::get().max_bytes_per_second.map(|rate| {
+            RateLimits::new(&RateLimitConfig {
+                max_bytes_per_second: Some(rate),
+                ..Default::default()
+            })
+        })
+    });
+    if let Some(throttle) = throttle {
+        throttle.received(bytes).await;
+    }
+
+    start.elapsed()
+}
+
 pub enum AudioFile {
     Cached(fs::File),
     Streaming(AudioFileStreaming),
@@ -137,6 +214,7 @@ pub struct StreamingRequest {
     initial_response: Option<Response<Incoming>>,
     offset: usize,
     length: usize,
+    url_index: usize,
 }
 
 #[derive(Debug)]
@@ -186,6 +264,13 @@ impl StreamLoaderController {
         }
     }
 
+    pub fn downloaded_len(&self) -> usize {
+        match self.stream_shared {
+            Some(ref shared) => shared.download_status.lock().downloaded.len(),
+            None => self.file_size,
+        }
+    }
+
     pub fn ping_time(&self) -> Option<Duration> {
         self.stream_shared.as_ref().map(|shared| shared.ping_time())
     }
@@ -307,6 +392,7 @@ struct AudioFileDownloadStatus {
 
 struct AudioFileShared {
     cdn_url: CdnUrl,
+    cdn_url_index: AtomicUsize,
     file_size: usize,
     bytes_per_second: usize,
     cond: Condvar,
@@ -319,6 +405,48 @@ struct AudioFileShared {
 }
 
 impl AudioFileShared {
+    // The CDN URL that new requests are made to
+    fn cdn_url(&self) -> Result<(usize, &str), Error> {
+        let index = self.cdn_url_index.load(Ordering::Acquire);
+        let (url_index, url) = self.cdn_url.try_get_url_from(index)?;
+        if url_index != index {
+            // skipped expired URLs, so failing over should start from this one
+            let _ = self.cdn_url_index.compare_exchange(
+                index,
+                url_index,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+        }
+        Ok((url_index, url))
+    }
+
+    // Moves on to the next CDN URL, unless another request already did so after the
+    // one at `index` let it down
+    fn fail_over(&self, index: usize, reason: &str) {
+        let count = self.cdn_url.url_count();
+        if count < 2 {
+            return;
+        }
+
+        let next = (index + 1) % count;
+        if self
+            .cdn_url_index
+            .compare_exchange(index, next, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            warn!("{reason}, failing over to CDN URL {} of {count}", next + 1);
+        }
+    }
+
+    // Fails over when the request to the URL at `index` failed because of the CDN. Requests
+    // held back by our own rate limits say nothing about the CDN.
+    fn request_failed(&self, index: usize, error: &Error) {
+        if !error.is_throttled() {
+            self.fail_over(index, "CDN failed");
+        }
+    }
+
     fn is_download_streaming(&self) -> bool {
         self.download_streaming.load(Ordering::Acquire)
     }
@@ -395,6 +523,30 @@ impl AudioFile {
         Ok(AudioFile::Streaming(streaming.await?))
     }
 
+    /// Streams from an already resolved `CdnUrl`, bypassing the cache.
+    pub async fn open_cdn_url(
+        session: &Session,
+        cdn_url: CdnUrl,
+        bytes_per_second: usize,
+    ) -> Result<AudioFile, Error> {
+        debug!("Downloading file {} from resolved CDN URL", cdn_url.file_id);
+
+        // there is no cache to store the complete file in
+        let (complete_tx, complete_rx) = oneshot::channel();
+        session.spawn(async move {
+            let _ = complete_rx.await;
+        });
+
+        let streaming = AudioFileStreaming::open_cdn_url(
+            session.clone(),
+            cdn_url,
+            complete_tx,
+            bytes_per_second,
+        );
+
+        Ok(AudioFile::Streaming(streaming.await?))
+    }
+
     pub fn get_stream_loader_controller(&self) -> Result<StreamLoaderController, Error> {
         let controller = match self {
             AudioFile::Streaming(ref stream) => StreamLoaderController {
@@ -425,35 +577,61 @@ impl AudioFileStreaming {
         bytes_per_second: usize,
     ) -> Result<AudioFileStreaming, Error> {
         let cdn_url = CdnUrl::new(file_id).resolve_audio(&session).await?;
+        Self::open_cdn_url(session, cdn_url, complete_tx, bytes_per_second).await
+    }
 
-        if let Ok(url) = cdn_url.try_get_url() {
-            trace!("Streaming from {}", url);
-        }
+    pub async fn open_cdn_url(
+        session: Session,
+        cdn_url: CdnUrl,
+        complete_tx: oneshot::Sender<NamedTempFile>,
+        bytes_per_second: usize,
+    ) -> Result<AudioFileStreaming, Error> {
+        // fail early when there is no URL left that has not expired
+        cdn_url.try_get_url()?;
 
         let minimum_download_size = AudioFetchParams::get().minimum_download_size;
 
-        // When the audio file is really small, this `download_size` may turn out to be
-        // larger than the audio file we're going to stream later on. This is OK; requesting
-        // `Content-Range` > `Content-Length` will return the complete file with status code
-        // 206 Partial Content.
-        let mut streamer =
-            session
-                .spclient()
-                .stream_from_cdn(&cdn_url, 0, minimum_download_size)?;
-
-        // Get the first chunk with the headers to get the file size.
-        // The remainder of that chunk with possibly also a response body is then
-        // further processed in `audio_file_fetch`.
-        let response = streamer.next().await.ok_or(AudioFileError::NoData)??;
+        // Any URL that fails is skipped for the next one. Transient failures, like being
+        // rate limited by the CDN, are retried a few times once all of them have failed.
+        let mut tries = 0;
+        let mut url_index = 0;
+        let (url_index, streamer, response) = 'open: loop {
+            tries += 1;
+
+            let mut error = None;
+            for _ in 0..cdn_url.url_count() {
+                let (index, url) = cdn_url.try_get_url_from(url_index)?;
+                trace!("Streaming from {}", url);
+
+                match Self::request_first_chunk(&session, url, minimum_download_size).await {
+                    Ok((streamer, response)) => break 'open (index, streamer, response),
+                    Err(e) if e.is_throttled() => {
+                        // the next URL would be held back just the same
+                        error = Some(e);
+                        break;
+                    }
+                    Err(e) => {
+                        if cdn_url.url_count() > 1 {
+                            warn!("Opening audio file from {url} failed: {e}, trying the next URL");
+                        }
+                        url_index = index + 1;
+                        error = Some(e);
+                    }
+                }
+            }
 
-        let code = response.status();
-        if code != StatusCode::PARTIAL_CONTENT {
-            debug!(
-                "Opening audio file expected partial content but got: {}",
-                code
-            );
-            return Err(AudioFileError::StatusCode(code).into());
-        }
+            match error {
+                Some(e) if e.is_retryable() && tries < MAX_OPEN_TRIES => {
+                    let delay = e
+                        .retry_after()
+                        .unwrap_or_else(|| http_client::backoff(tries));
+                    warn!("Opening audio file failed: {e}, retrying in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                }
+                Some(e) => return Err(e),
+                None => return Err(AudioFileError::NoData.into()),
+            }
+        };
 
         let header_value = response
             .headers()
@@ -470,10 +648,12 @@ impl AudioFileStreaming {
             initial_response: Some(response),
             offset: 0,
             length: upper_bound + 1,
+            url_index,
         };
 
         let shared = Arc::new(AudioFileShared {
             cdn_url,
+            cdn_url_index: AtomicUsize::new(url_index),
             file_size,
             bytes_per_second,
             cond: Condvar::new(),
@@ -482,7 +662,7 @@ impl AudioFileStreaming {
                 downloaded: RangeSet::new(),
             }),
             download_streaming: AtomicBool::new(false),
-            download_slots: Semaphore::new(1),
+            download_slots: Semaphore::new(AudioFetchParams::get().parallel_downloads.max(1)),
             ping_time_ms: AtomicUsize::new(0),
             read_position: AtomicUsize::new(0),
             throughput: AtomicUsize::new(0),
@@ -514,6 +694,37 @@ impl AudioFileStreaming {
     }
 }
 
+impl AudioFileStreaming {
+    // Requests the start of the file, whose headers tell the size of the file
+    async fn request_first_chunk(
+        session: &Session,
+        url: &str,
+        length: usize,
+    ) -> Result<(IntoStream<ResponseFuture>, Response<Incoming>), Error> {
+        // When the audio file is really small, this `download_size` may turn out to be
+        // larger than the audio file we're going to stream later on. This is OK; requesting
+        // `Content-Range` > `Content-Length` will return the complete file with status code
+        // 206 Partial Content.
+        let mut streamer = session.spclient().stream_from_cdn_url(url, 0, length)?;
+
+        // Get the first chunk with the headers to get the file size.
+        // The remainder of that chunk with possibly also a response body is then
+        // further processed in `audio_file_fetch`.
+        let response = streamer.next().await.ok_or(AudioFileError::NoData)??;
+
+        let code = response.status();
+        if code != StatusCode::PARTIAL_CONTENT {
+            debug!(
+                "Opening audio file expected partial content but got: {}",
+                code
+            );
+            return Err(status_error(&response));
+        }
+
+        Ok((streamer, response))
+    }
+}
+
 impl Read for AudioFileStreaming {
     fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
         let offset = self.position as usize;
@@ -645,3 +856,87 @@ impl Seek for AudioFile {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use librespot_core::{
+        cdn_url::{MaybeExpiringUrl, MaybeExpiringUrls},
+        http_client::HttpClientError,
+    };
+
+    fn shared(urls: &[&str]) -> AudioFileShared {
+        let urls = urls
+            .iter()
+            .map(|url| MaybeExpiringUrl(url.to_string(), None))
+            .collect();
+
+        AudioFileShared {
+            cdn_url: CdnUrl::with_urls(FileId::from_raw(&[0; 20]), MaybeExpiringUrls(urls)),
+            cdn_url_index: AtomicUsize::new(0),
+            file_size: 0,
+            bytes_per_second: 0,
+            cond: Condvar::new(),
+            download_status: Mutex::new(AudioFileDownloadStatus {
+                requested: RangeSet::new(),
+                downloaded: RangeSet::new(),
+            }),
+            download_streaming: AtomicBool::new(false),
+            download_slots: Semaphore::new(1),
+            ping_time_ms: AtomicUsize::new(0),
+            read_position: AtomicUsize::new(0),
+            throughput: AtomicUsize::new(0),
+        }
+    }
+
+    #[test]
+    fn fails_over_to_the_next_url() {
+        let shared = shared(&["https://a.example", "https://b.example"]);
+        assert_eq!(shared.cdn_url().unwrap(), (0, "https://a.example"));
+
+        shared.fail_over(0, "test");
+        assert_eq!(shared.cdn_url().unwrap(), (1, "https://b.example"));
+
+        shared.fail_over(1, "test");
+        assert_eq!(shared.cdn_url().unwrap(), (0, "https://a.example"));
+    }
+
+    #[test]
+    fn fails_over_once_for_requests_to_the_same_url() {
+        let shared = shared(&[
+            "https://a.example",
+            "https://b.example",
+            "https://c.example",
+        ]);
+
+        // parallel requests to the first URL failing should not skip the second
+        shared.fail_over(0, "test");
+        shared.fail_over(0, "test");
+        assert_eq!(shared.cdn_url().unwrap(), (1, "https://b.example"));
+    }
+
+    #[test]
+    fn stays_with_a_single_url() {
+        let shared = shared(&["https://a.example"]);
+        shared.fail_over(0, "test");
+        assert_eq!(shared.cdn_url().unwrap(), (0, "https://a.example"));
+    }
+
+    #[test]
+    fn only_fails_over_on_errors_of_the_cdn() {
+        let shared = shared(&["https://a.example", "https://b.example"]);
+
+        let throttled = HttpClientError::Throttled(Duration::from_secs(1)).into();
+        shared.request_failed(0, &throttled);
+        assert_eq!(shared.cdn_url().unwrap().0, 0);
+
+        let rate_limited =
+            HttpClientError::RetryAfter(StatusCode::TOO_MANY_REQUESTS, Duration::from_secs(1));
+        shared.request_failed(0, &rate_limited.into());
+        assert_eq!(shared.cdn_url().unwrap().0, 1);
+
+        shared.request_failed(1, &AudioFileError::WaitTimeout.into());
+        assert_eq!(shared.cdn_url().unwrap().0, 0);
+    }
+}
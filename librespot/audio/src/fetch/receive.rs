@@ -12,13 +12,13 @@ use hyper::StatusCode;
 use tempfile::NamedTempFile;
 use tokio::sync::{mpsc, oneshot};
 
-use librespot_core::{http_client::HttpClient, session::Session, Error};
+use librespot_core::{http_client::RATE_LIMIT_MAX_WAIT, session::Session, Error};
 
 use crate::range_set::{Range, RangeSet};
 
 use super::{
-    AudioFetchParams, AudioFileError, AudioFileResult, AudioFileShared, StreamLoaderCommand,
-    StreamingRequest,
+    status_error, throttle, AudioFetchParams, AudioFileError, AudioFileResult, AudioFileShared,
+    StreamLoaderCommand, StreamingRequest,
 };
 
 struct PartialFileData {
@@ -35,6 +35,7 @@ enum ReceivedData {
 const ONE_SECOND: Duration = Duration::from_secs(1);
 
 async fn receive_data(
+    session: Session,
     shared: Arc<AudioFileShared>,
     file_data_tx: mpsc::UnboundedSender<ReceivedData>,
     mut request: StreamingRequest,
@@ -44,10 +45,14 @@ async fn receive_data(
 
     let permit = shared.download_slots.acquire().await?;
 
+    let params = AudioFetchParams::get();
     let request_time = Instant::now();
     let mut measure_ping_time = true;
     let mut measure_throughput = true;
 
+    // time spent holding back for the rate limits doesn't count against the CDN's throughput
+    let mut throttled = Duration::ZERO;
+
     let result: Result<_, Error> = loop {
         let response = match request.initial_response.take() {
             Some(data) => {
@@ -82,41 +87,69 @@ async fn receive_data(
 
         let code = response.status();
         if code != StatusCode::PARTIAL_CONTENT {
-            if code == StatusCode::TOO_MANY_REQUESTS {
-                if let Some(duration) = HttpClient::get_retry_after(response.headers()) {
-                    warn!(
-                        "Rate limiting, retrying in {} seconds...",
-                        duration.as_secs()
-                    );
-                    // sleeping here means we hold onto this streamer "slot"
-                    // (we don't decrease the number of open requests)
-                    tokio::time::sleep(duration).await;
-                }
+            let err = status_error(&response);
+            if let Some(duration) = err.retry_after().filter(|d| *d <= RATE_LIMIT_MAX_WAIT) {
+                warn!(
+                    "Rate limiting, retrying in {} seconds...",
+                    duration.as_secs()
+                );
+                // sleeping here means we hold onto this streamer "slot"
+                // (we don't decrease the number of open requests)
+                tokio::time::sleep(duration).await;
             }
 
-            break Err(AudioFileError::StatusCode(code).into());
+            break Err(err);
         }
 
-        let body = response.into_body();
-        let data = match body.collect().await.map(|b| b.to_bytes()) {
-            Ok(bytes) => bytes,
-            Err(e) => break Err(e.into()),
-        };
+        // The body is passed on as it comes in rather than collected, so throttling holds back
+        // the connection itself instead of letting a whole block through at once.
+        let mut body = response.into_body();
+        let received: Result<_, Error> = loop {
+            // a CDN that stops sending is given up on rather than waited for
+            let frame = match tokio::time::timeout(params.download_timeout, body.frame()).await {
+                Ok(frame) => frame,
+                Err(_) => break Err(AudioFileError::WaitTimeout.into()),
+            };
 
-        let data_size = data.len();
-        file_data_tx.send(ReceivedData::Data(PartialFileData { offset, data }))?;
+            let data = match frame {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => data,
+                    Err(_) => continue,
+                },
+                Some(Err(e)) => break Err(e.into()),
+                None => break Ok(()),
+            };
 
-        actual_length += data_size;
-        offset += data_size;
+            let data_size = data.len();
+            throttled += throttle(&session, data_size).await;
+            file_data_tx.send(ReceivedData::Data(PartialFileData { offset, data }))?;
+
+            actual_length += data_size;
+            offset += data_size;
+        };
+
+        if let Err(e) = received {
+            break Err(e);
+        }
     };
 
     drop(request.streamer);
 
     if measure_throughput {
-        let duration = Instant::now().duration_since(request_time).as_millis();
+        let duration = Instant::now()
+            .duration_since(request_time)
+            .saturating_sub(throttled)
+            .as_millis();
         if actual_length > 0 && duration > 0 {
             let throughput = ONE_SECOND.as_millis() as usize * actual_length / duration as usize;
             file_data_tx.send(ReceivedData::Throughput(throughput))?;
+
+            // short requests are dominated by the ping time, so only judge full blocks
+            if actual_length >= params.minimum_download_size
+                && throughput < params.minimum_throughput
+            {
+                shared.fail_over(request.url_index, "CDN is slow");
+            }
         }
     }
 
@@ -137,6 +170,7 @@ async fn receive_data(
             "Streamer error requesting range {} +{}: {:?}",
             request.offset, request.length, e
         );
+        shared.request_failed(request.url_index, &e);
         return Err(e);
     }
 
@@ -197,26 +231,34 @@ impl AudioFileFetch {
         // TODO : refresh cdn_url when the token expired
 
         for range in ranges_to_request.iter() {
-            let streamer = self.session.spclient().stream_from_cdn(
-                &self.shared.cdn_url,
-                range.start,
-                range.length,
-            )?;
-
-            download_status.requested.add_range(range);
-
-            let streaming_request = StreamingRequest {
-                streamer,
-                initial_response: None,
-                offset: range.start,
-                length: range.length,
-            };
+            // with parallel downloads, large ranges are fetched in parts at the same time
+            for part in range.split(
+                self.params.parallel_downloads,
+                self.params.minimum_download_size,
+            ) {
+                let (url_index, url) = self.shared.cdn_url()?;
+                let streamer =
+                    self.session
+                        .spclient()
+                        .stream_from_cdn_url(url, part.start, part.length)?;
+
+                download_status.requested.add_range(&part);
+
+                let streaming_request = StreamingRequest {
+                    streamer,
+                    initial_response: None,
+                    offset: part.start,
+                    length: part.length,
+                    url_index,
+                };
 
-            self.session.spawn(receive_data(
-                self.shared.clone(),
-                self.file_data_tx.clone(),
-                streaming_request,
-            ));
+                self.session.spawn(receive_data(
+                    self.session.clone(),
+                    self.shared.clone(),
+                    self.file_data_tx.clone(),
+                    streaming_request,
+                ));
+            }
         }
 
         Ok(())
@@ -420,6 +462,7 @@ pub(super) async fn audio_file_fetch(
     }
 
     session.spawn(receive_data(
+        session.clone(),
         shared.clone(),
         file_data_tx.clone(),
         initial_request,
@@ -4,7 +4,7 @@ use std::{
     slice::Iter,
 };
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Range {
     pub start: usize,
     pub length: usize,
@@ -24,6 +24,28 @@ impl Range {
     pub fn end(&self) -> usize {
         self.start + self.length
     }
+
+    /// Splits the range into at most `parts` disjoint ranges of at least `minimum_length`
+    /// each, which together cover the range.
+    pub fn split(&self, parts: usize, minimum_length: usize) -> Vec<Range> {
+        let parts = min(parts, self.length / max(minimum_length, 1));
+        if parts <= 1 {
+            return vec![*self];
+        }
+
+        let part_length = self.length / parts;
+        (0..parts)
+            .map(|i| {
+                let start = self.start + i * part_length;
+                let length = if i == parts - 1 {
+                    self.end() - start
+                } else {
+                    part_length
+                };
+                Range::new(start, length)
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -241,3 +263,42 @@ impl RangeSet {
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_into_at_most_one_part() {
+        let range = Range::new(100, 1000);
+        assert_eq!(range.split(0, 10), vec![range]);
+        assert_eq!(range.split(1, 10), vec![range]);
+    }
+
+    #[test]
+    fn split_keeps_parts_above_the_minimum_length() {
+        let range = Range::new(100, 1000);
+        assert_eq!(range.split(4, 2000), vec![range]);
+
+        let parts = range.split(4, 400);
+        assert_eq!(parts, vec![Range::new(100, 500), Range::new(600, 500)]);
+
+        // a minimum of zero is taken as one
+        assert_eq!(Range::new(0, 3).split(8, 0).len(), 3);
+    }
+
+    #[test]
+    fn split_gives_the_remainder_to_the_last_part() {
+        let parts = Range::new(10, 1003).split(4, 1);
+        assert_eq!(
+            parts,
+            vec![
+                Range::new(10, 250),
+                Range::new(260, 250),
+                Range::new(510, 250),
+                Range::new(760, 253),
+            ]
+        );
+        assert_eq!(parts.last().unwrap().end(), 1013);
+    }
+}
@@ -111,7 +111,9 @@ impl SizeLimiter {
     }
 }
 
-struct FsSizeLimiter {
+/// Keeps the files in a directory under a size limit, removing the least recently used ones
+/// when it is exceeded. Files have to be reported with `add` and `touch` when written and read.
+pub struct FsSizeLimiter {
     limiter: Mutex<SizeLimiter>,
 }
 
@@ -188,15 +190,18 @@ impl FsSizeLimiter {
         }
     }
 
-    fn add(&self, file: &Path, size: u64) {
+    /// Records a file that was just written.
+    pub fn add(&self, file: &Path, size: u64) {
         self.limiter.lock().add(file, size, SystemTime::now())
     }
 
-    fn touch(&self, file: &Path) -> bool {
+    /// Marks a file as just used. Returns whether it is known.
+    pub fn touch(&self, file: &Path) -> bool {
         self.limiter.lock().update(file, SystemTime::now())
     }
 
-    fn remove(&self, file: &Path) -> bool {
+    /// Forgets a file that was removed. Returns whether it was known.
+    pub fn remove(&self, file: &Path) -> bool {
         self.limiter.lock().remove(file)
     }
 
@@ -231,11 +236,14 @@ impl FsSizeLimiter {
         }
     }
 
-    fn prune(&self) -> Result<(), Error> {
+    /// Removes the least recently used files until the limit is met.
+    pub fn prune(&self) -> Result<(), Error> {
         Self::prune_internal(|| self.limiter.lock().pop())
     }
 
-    fn new(path: &Path, limit: u64) -> Result<Self, Error> {
+    /// Creates a limiter for the files already in `path`, removing old ones right away if they
+    /// exceed `limit` bytes.
+    pub fn new(path: &Path, limit: u64) -> Result<Self, Error> {
         let mut limiter = SizeLimiter::new(limit);
 
         Self::init_dir(&mut limiter, path);
@@ -65,6 +65,10 @@ impl CdnUrl {
         }
     }
 
+    pub fn with_urls(file_id: FileId, urls: MaybeExpiringUrls) -> Self {
+        Self { file_id, urls }
+    }
+
     pub async fn resolve_audio(&self, session: &Session) -> Result<Self, Error> {
         let file_id = self.file_id;
         let response = session.spclient().get_audio_storage(&file_id).await?;
@@ -79,22 +83,36 @@ impl CdnUrl {
     }
 
     pub fn try_get_url(&self) -> Result<&str, Error> {
+        self.try_get_url_from(0).map(|(_, url)| url)
+    }
+
+    /// Returns the first URL that has not expired, starting at `index` and wrapping around, and
+    /// its index. This lets callers move on to the next URL when one doesn't work well.
+    pub fn try_get_url_from(&self, index: usize) -> Result<(usize, &str), Error> {
         if self.urls.is_empty() {
             return Err(CdnUrlError::Unresolved.into());
         }
 
         let now = Date::now_utc();
-        let url = self.urls.iter().find(|url| match url.1 {
-            Some(expiry) => now < expiry,
-            None => true,
-        });
+        let count = self.urls.len();
+        let url = (0..count)
+            .map(|offset| (index + offset) % count)
+            .find(|&i| match self.urls[i].1 {
+                Some(expiry) => now < expiry,
+                None => true,
+            });
 
-        if let Some(url) = url {
-            Ok(&url.0)
+        if let Some(i) = url {
+            Ok((i, &self.urls[i].0))
         } else {
             Err(CdnUrlError::Expired.into())
         }
     }
+
+    /// The number of URLs the file can be fetched from, including expired ones.
+    pub fn url_count(&self) -> usize {
+        self.urls.len()
+    }
 }
 
 impl TryFrom<CdnUrlMessage> for MaybeExpiringUrls {
@@ -217,4 +235,31 @@ mod test {
             timestamp_margin.whole_milliseconds()
         );
     }
+
+    #[test]
+    fn test_try_get_url_from() {
+        let expired = Date::from_timestamp_ms(1_000).ok();
+        let cdn_url = CdnUrl::with_urls(
+            FileId::from_raw(&[0; 20]),
+            MaybeExpiringUrls(vec![
+                MaybeExpiringUrl("https://a.example".to_owned(), None),
+                MaybeExpiringUrl("https://b.example".to_owned(), expired),
+                MaybeExpiringUrl("https://c.example".to_owned(), None),
+            ]),
+        );
+
+        assert_eq!(cdn_url.url_count(), 3);
+        assert_eq!(cdn_url.try_get_url().unwrap(), "https://a.example");
+        assert_eq!(
+            cdn_url.try_get_url_from(1).unwrap(),
+            (2, "https://c.example")
+        );
+        assert_eq!(
+            cdn_url.try_get_url_from(3).unwrap(),
+            (0, "https://a.example")
+        );
+
+        let unresolved = CdnUrl::new(FileId::from_raw(&[0; 20]));
+        assert!(unresolved.try_get_url_from(0).is_err());
+    }
 }
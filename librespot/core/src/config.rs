@@ -1,8 +1,12 @@
-use std::{fmt, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap, fmt, num::NonZeroU32, path::PathBuf, str::FromStr, time::Duration,
+};
 
 use librespot_protocol::devices::DeviceType as ProtoDeviceType;
 use url::Url;
 
+use crate::http_client::{RATE_LIMIT_CALLS_PER_INTERVAL, RATE_LIMIT_INTERVAL};
+
 pub(crate) const KEYMASTER_CLIENT_ID: &str = "65b708073fc0480ea92a077233ca87bd";
 pub(crate) const ANDROID_CLIENT_ID: &str = "9a8d2f0ce77a4e248bb71fefcb557637";
 pub(crate) const IOS_CLIENT_ID: &str = "58bd3c95768941ea9eb4350aaa033eb3";
@@ -29,6 +33,7 @@ pub struct SessionConfig {
     pub ap_port: Option<u16>,
     pub tmp_dir: PathBuf,
     pub autoplay: Option<bool>,
+    pub rate_limits: RateLimitConfig,
 }
 
 impl SessionConfig {
@@ -48,6 +53,7 @@ impl SessionConfig {
             ap_port: None,
             tmp_dir: std::env::temp_dir(),
             autoplay: None,
+            rate_limits: RateLimitConfig::default(),
         }
     }
 }
@@ -58,6 +64,67 @@ impl Default for SessionConfig {
     }
 }
 
+/// How many requests may be made in an interval. They may all be made at once, after which
+/// they are spread evenly over the interval.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestRate {
+    pub calls: NonZeroU32,
+    pub interval: Duration,
+}
+
+impl RequestRate {
+    pub const fn new(calls: NonZeroU32, interval: Duration) -> Self {
+        Self { calls, interval }
+    }
+
+    pub const fn per_second(calls: NonZeroU32) -> Self {
+        Self::new(calls, Duration::from_secs(1))
+    }
+
+    pub const fn per_minute(calls: NonZeroU32) -> Self {
+        Self::new(calls, Duration::from_secs(60))
+    }
+}
+
+/// How fast the HTTP client makes requests and receives responses, see
+/// [`RateLimits`](crate::rate_limit::RateLimits). Requests are held back until every budget
+/// that applies to them allows it.
+#[derive(Clone, Debug)]
+pub struct RateLimitConfig {
+    /// The budget of each host that has none of its own in `hosts`. Subdomains share the budget
+    /// of their domain, so `spclient.wg.spotify.com` and `api.spotify.com` count as
+    /// `spotify.com`.
+    pub per_host: RequestRate,
+
+    /// Budgets of particular hosts or domains, used instead of `per_host`.
+    pub hosts: HashMap<String, RequestRate>,
+
+    /// A budget shared by all requests to the Spotify APIs, like metadata, whichever host they go
+    /// to.
+    pub metadata: Option<RequestRate>,
+
+    /// A budget shared by all requests for audio files from the CDNs.
+    pub cdn: Option<RequestRate>,
+
+    /// The most response bytes received per second, over all requests.
+    pub max_bytes_per_second: Option<NonZeroU32>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let calls = NonZeroU32::new(RATE_LIMIT_CALLS_PER_INTERVAL)
+            .expect("default rate limit should allow calls");
+
+        Self {
+            per_host: RequestRate::new(calls, RATE_LIMIT_INTERVAL),
+            hosts: HashMap::new(),
+            metadata: None,
+            cdn: None,
+            max_bytes_per_second: None,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Hash, PartialOrd, Ord, PartialEq, Eq, Default)]
 pub enum DeviceType {
     Unknown = 0,
@@ -3,6 +3,7 @@ use std::{
     num::{ParseIntError, TryFromIntError},
     str::Utf8Error,
     string::FromUtf8Error,
+    time::Duration,
 };
 
 use base64::DecodeError;
@@ -11,6 +12,7 @@ use http::{
     method::InvalidMethod,
     status::InvalidStatusCode,
     uri::{InvalidUri, InvalidUriParts},
+    StatusCode,
 };
 use protobuf::Error as ProtobufError;
 use thiserror::Error;
@@ -21,6 +23,8 @@ use url::ParseError;
 
 use librespot_oauth::OAuthError;
 
+use crate::http_client::HttpClientError;
+
 #[derive(Debug)]
 pub struct Error {
     pub kind: ErrorKind,
@@ -270,6 +274,39 @@ impl Error {
             error: error.into(),
         }
     }
+
+    /// Whether the error is transient, so that trying the same again later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        if let Some(HttpClientError::StatusCode(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS)) =
+            self.error.downcast_ref()
+        {
+            return false;
+        }
+
+        matches!(
+            self.kind,
+            ErrorKind::DeadlineExceeded | ErrorKind::ResourceExhausted | ErrorKind::Unavailable
+        )
+    }
+
+    /// How long the service, or the rate limits of the HTTP client, asked to wait before
+    /// trying again, if they did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self.error.downcast_ref() {
+            Some(HttpClientError::RetryAfter(_, duration))
+            | Some(HttpClientError::Throttled(duration)) => Some(*duration),
+            _ => None,
+        }
+    }
+
+    /// Whether the request was held back by the rate limits of the HTTP client, so that it
+    /// never reached the service.
+    pub fn is_throttled(&self) -> bool {
+        matches!(
+            self.error.downcast_ref(),
+            Some(HttpClientError::Throttled(_))
+        )
+    }
 }
 
 impl std::error::Error for Error {
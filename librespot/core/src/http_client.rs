@@ -1,13 +1,13 @@
 use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
 };
 
 use bytes::Bytes;
-use futures_util::{future::IntoStream, FutureExt};
-use governor::{
-    clock::MonotonicClock, middleware::NoOpMiddleware, state::InMemoryState, Quota, RateLimiter,
-};
+use futures_util::{future::IntoStream, FutureExt, TryFutureExt};
 use http::{header::HeaderValue, Uri};
 use http_body_util::{BodyExt, Full};
 use hyper::{body::Incoming, header::USER_AGENT, HeaderMap, Request, Response, StatusCode};
@@ -15,38 +15,54 @@ use hyper_proxy2::{Intercept, Proxy, ProxyConnector};
 use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use hyper_util::{
     client::legacy::{connect::HttpConnector, Client, ResponseFuture},
-    rt::TokioExecutor,
+    rt::{TokioExecutor, TokioIo},
 };
-use nonzero_ext::nonzero;
 use once_cell::sync::OnceCell;
-use parking_lot::Mutex;
+use rand::Rng;
 use thiserror::Error;
+use tokio::net::TcpStream;
+use tower_service::Service;
 use url::Url;
 
 use crate::{
-    config::{os_version, OS},
+    config::{os_version, RateLimitConfig, OS},
     date::Date,
+    proxytunnel,
+    rate_limit::{Budget, RateLimits},
+    socket,
     version::{spotify_version, FALLBACK_USER_AGENT, VERSION_STRING},
     Error,
 };
 
-// The 30 seconds interval is documented by Spotify, but the calls per interval
-// is a guesstimate and probably subject to licensing (purchasing extra calls)
-// and may change at any time.
+// The default per host budget of `RateLimitConfig`. The 30 seconds interval is
+// documented by Spotify, but the calls per interval is a guesstimate and probably
+// subject to licensing (purchasing extra calls) and may change at any time.
 pub const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(30);
 pub const RATE_LIMIT_MAX_WAIT: Duration = Duration::from_secs(10);
 pub const RATE_LIMIT_CALLS_PER_INTERVAL: u32 = 300;
 
+// How often a rate limited request is tried before the error is left to the caller
+pub const RATE_LIMIT_MAX_TRIES: usize = 3;
+
+// Bounds of the exponential backoff between retries, see `backoff()`
+const BACKOFF_BASE: Duration = Duration::from_millis(200);
+const BACKOFF_MAX: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Error)]
 pub enum HttpClientError {
     #[error("Response status code: {0}")]
     StatusCode(hyper::StatusCode),
+    #[error("Response status code: {0}, retry after {secs} seconds", secs = .1.as_secs())]
+    RetryAfter(hyper::StatusCode, Duration),
+    #[error("Held back by the rate limits for {secs} seconds", secs = .0.as_secs())]
+    Throttled(Duration),
 }
 
 impl From<HttpClientError> for Error {
     fn from(err: HttpClientError) -> Self {
         match err {
-            HttpClientError::StatusCode(code) => {
+            HttpClientError::Throttled(_) => Error::resource_exhausted(err),
+            HttpClientError::StatusCode(code) | HttpClientError::RetryAfter(code, _) => {
                 // not exhaustive, but what reasonably could be expected
                 match code {
                     StatusCode::GATEWAY_TIMEOUT | StatusCode::REQUEST_TIMEOUT => {
@@ -89,17 +105,57 @@ impl From<HttpClientError> for Error {
     }
 }
 
-type HyperClient = Client<ProxyConnector<HttpsConnector<HttpConnector>>, Full<bytes::Bytes>>;
+type HyperClient = Client<ProxyConnector<HttpsConnector<Connector>>, Full<bytes::Bytes>>;
+
+// Opens the connections of the hyper client, either directly or through a SOCKS proxy. HTTP
+// proxies are left to the `ProxyConnector` around it.
+#[derive(Clone)]
+enum Connector {
+    Direct(HttpConnector),
+    Socks(Url),
+}
+
+impl Service<Uri> for Connector {
+    type Response = TokioIo<TcpStream>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            Self::Direct(connector) => connector.poll_ready(cx).map_err(io::Error::other),
+            Self::Socks(_) => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        match self {
+            Self::Direct(connector) => Box::pin(connector.call(uri).map_err(io::Error::other)),
+            Self::Socks(proxy_url) => {
+                let proxy_url = proxy_url.clone();
+                Box::pin(async move {
+                    let host = uri
+                        .host()
+                        .ok_or_else(|| io::Error::other("URI has no host"))?
+                        .trim_matches(['[', ']']);
+                    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+                        Some("https") => 443,
+                        _ => 80,
+                    });
+
+                    let socket = socket::connect_proxy(&proxy_url).await?;
+                    let socket = proxytunnel::socks_connect(socket, &proxy_url, host, port).await?;
+                    Ok(TokioIo::new(socket))
+                })
+            }
+        }
+    }
+}
 
 pub struct HttpClient {
     user_agent: HeaderValue,
     proxy_url: Option<Url>,
     hyper_client: OnceCell<HyperClient>,
-
-    // while the DashMap variant is more performant, our level of concurrency
-    // is pretty low so we can save pulling in that extra dependency
-    rate_limiter:
-        RateLimiter<String, Mutex<HashMap<String, InMemoryState>>, MonotonicClock, NoOpMiddleware>,
+    rate_limits: RateLimits,
 }
 
 impl HttpClient {
@@ -128,21 +184,24 @@ impl HttpClient {
             HeaderValue::from_static(FALLBACK_USER_AGENT)
         });
 
-        let replenish_interval_ns =
-            RATE_LIMIT_INTERVAL.as_nanos() / RATE_LIMIT_CALLS_PER_INTERVAL as u128;
-        let quota = Quota::with_period(Duration::from_nanos(replenish_interval_ns as u64))
-            .expect("replenish interval should be valid")
-            .allow_burst(nonzero![RATE_LIMIT_CALLS_PER_INTERVAL]);
-        let rate_limiter = RateLimiter::keyed(quota);
-
         Self {
             user_agent,
             proxy_url: proxy_url.cloned(),
             hyper_client: OnceCell::new(),
-            rate_limiter,
+            rate_limits: RateLimits::new(&RateLimitConfig::default()),
         }
     }
 
+    pub fn with_rate_limits(mut self, config: &RateLimitConfig) -> Self {
+        self.rate_limits = RateLimits::new(config);
+        self
+    }
+
+    /// The rate limits requests are held to, for example to see how long they are waiting.
+    pub fn rate_limits(&self) -> &RateLimits {
+        &self.rate_limits
+    }
+
     fn try_create_hyper_client(proxy_url: Option<&Url>) -> Result<HyperClient, Error> {
         // configuring TLS is expensive and should be done once per process
 
@@ -154,13 +213,30 @@ impl HttpClient {
         #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
         let tls = HttpsConnectorBuilder::new().with_webpki_roots();
 
-        let https_connector = tls.https_or_http().enable_http1().enable_http2().build();
-
-        // When not using a proxy a dummy proxy is configured that will not intercept any traffic.
-        // This prevents needing to carry the Client Connector generics through the whole project
+        let connector = match proxy_url {
+            Some(proxy_url) if proxytunnel::is_socks(proxy_url) => {
+                Connector::Socks(proxy_url.clone())
+            }
+            _ => {
+                let mut connector = HttpConnector::new();
+                connector.enforce_http(false);
+                Connector::Direct(connector)
+            }
+        };
+        let https_connector = tls
+            .https_or_http()
+            .enable_http1()
+            .enable_http2()
+            .wrap_connector(connector);
+
+        // When not using an HTTP proxy a dummy proxy is configured that will not intercept any
+        // traffic. This prevents needing to carry the Client Connector generics through the
+        // whole project
         let proxy = match &proxy_url {
-            Some(proxy_url) => Proxy::new(Intercept::All, proxy_url.to_string().parse()?),
-            None => Proxy::new(Intercept::None, Uri::from_static("0.0.0.0")),
+            Some(proxy_url) if !proxytunnel::is_socks(proxy_url) => {
+                Proxy::new(Intercept::All, proxy_url.to_string().parse()?)
+            }
+            _ => Proxy::new(Intercept::None, Uri::from_static("0.0.0.0")),
         };
         let proxy_connector = ProxyConnector::from_proxy(https_connector, proxy)?;
 
@@ -183,33 +259,45 @@ impl HttpClient {
         // ourselves, as any `Request` is moved in the loop.
         let (parts, body_as_bytes) = req.into_parts();
 
+        let mut tries = 0;
         loop {
+            tries += 1;
+
             let mut req = Request::builder()
                 .method(parts.method.clone())
                 .uri(parts.uri.clone())
                 .version(parts.version)
                 .body(body_as_bytes.clone())?;
             *req.headers_mut() = parts.headers.clone();
+            *req.extensions_mut() = parts.extensions.clone();
 
-            let request = self.request_fut(req)?;
+            // unlike `request_fut`, wait for the rate limits rather than fail
+            let host = req.uri().host().unwrap_or_default();
+            let budget = req.extensions().get::<Budget>().copied();
+            self.rate_limits.until_ready(host, budget).await;
+
+            let request = self.send(req)?;
             let response = request.await;
 
             if let Ok(response) = &response {
                 let code = response.status();
 
-                if code == StatusCode::TOO_MANY_REQUESTS {
-                    if let Some(duration) = Self::get_retry_after(response.headers()) {
-                        warn!(
-                            "Rate limited by service, retrying in {} seconds...",
-                            duration.as_secs()
-                        );
-                        tokio::time::sleep(duration).await;
-                        continue;
+                if !code.is_success() {
+                    let err = Self::status_error(code, response.headers());
+
+                    // short waits are sat out here, longer ones are up to the caller
+                    if let Some(duration) = err.retry_after() {
+                        if duration <= RATE_LIMIT_MAX_WAIT && tries < RATE_LIMIT_MAX_TRIES {
+                            warn!(
+                                "Rate limited by service, retrying in {} seconds...",
+                                duration.as_secs()
+                            );
+                            tokio::time::sleep(duration).await;
+                            continue;
+                        }
                     }
-                }
 
-                if !code.is_success() {
-                    return Err(HttpClientError::StatusCode(code).into());
+                    return Err(err);
                 }
             }
 
@@ -220,43 +308,69 @@ impl HttpClient {
 
     pub async fn request_body(&self, req: Request<Bytes>) -> Result<Bytes, Error> {
         let response = self.request(req).await?;
-        Ok(response.into_body().collect().await?.to_bytes())
+        let body = response.into_body().collect().await?.to_bytes();
+        self.rate_limits.received(body.len()).await;
+        Ok(body)
     }
 
     pub fn request_stream(&self, req: Request<Bytes>) -> Result<IntoStream<ResponseFuture>, Error> {
         Ok(self.request_fut(req)?.into_stream())
     }
 
-    pub fn request_fut(&self, mut req: Request<Bytes>) -> Result<ResponseFuture, Error> {
+    /// Makes the request right away, failing with a retryable error when the rate limits
+    /// don't allow it yet, see [`Error::is_throttled`]. The budget it counts against can be put in its extensions.
+    pub fn request_fut(&self, req: Request<Bytes>) -> Result<ResponseFuture, Error> {
+        let host = req.uri().host().unwrap_or_default();
+        let budget = req.extensions().get::<Budget>().copied();
+        self.rate_limits
+            .check(host, budget)
+            .map_err(HttpClientError::Throttled)?;
+
+        self.send(req)
+    }
+
+    fn send(&self, mut req: Request<Bytes>) -> Result<ResponseFuture, Error> {
         let headers_mut = req.headers_mut();
         headers_mut.insert(USER_AGENT, self.user_agent.clone());
 
-        // For rate limiting we cannot *just* depend on Spotify sending us HTTP/429
-        // Retry-After headers. For example, when there is a service interruption
-        // and HTTP/500 is returned, we don't want to DoS the Spotify infrastructure.
-        let domain = match req.uri().host() {
-            Some(host) => {
-                // strip the prefix from *.domain.tld (assume rate limit is per domain, not subdomain)
-                let mut parts = host
-                    .split('.')
-                    .map(|s| s.to_string())
-                    .collect::<Vec<String>>();
-                let n = parts.len().saturating_sub(2);
-                parts.drain(n..).collect()
+        Ok(self.hyper_client()?.request(req.map(Full::new)))
+    }
+
+    /// The error for a response with an unsuccessful status `code`. Rate limited and
+    /// unavailable responses carry the time the service asked to wait before trying again,
+    /// if it did, see [`Error::retry_after`].
+    pub fn status_error(code: StatusCode, headers: &HeaderMap<HeaderValue>) -> Error {
+        let retry_after = match code {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                Self::parse_retry_after(headers)
             }
-            None => String::from(""),
+            _ => None,
         };
-        self.rate_limiter.check_key(&domain).map_err(|e| {
-            Error::resource_exhausted(format!(
-                "rate limited for at least another {} seconds",
-                e.wait_time_from(Instant::now()).as_secs()
-            ))
-        })?;
 
-        Ok(self.hyper_client()?.request(req.map(Full::new)))
+        match retry_after {
+            Some(duration) => HttpClientError::RetryAfter(code, duration).into(),
+            None => HttpClientError::StatusCode(code).into(),
+        }
     }
 
+    /// Like [`HttpClient::parse_retry_after`], but only if it is not longer than
+    /// `RATE_LIMIT_MAX_WAIT`.
     pub fn get_retry_after(headers: &HeaderMap<HeaderValue>) -> Option<Duration> {
+        let duration = Self::parse_retry_after(headers)?;
+        if duration > RATE_LIMIT_MAX_WAIT {
+            debug!(
+                "Waiting {} seconds would exceed {} second limit",
+                duration.as_secs(),
+                RATE_LIMIT_MAX_WAIT.as_secs()
+            );
+            return None;
+        }
+
+        Some(duration)
+    }
+
+    /// The time the service asked to wait before making another request, if any.
+    pub fn parse_retry_after(headers: &HeaderMap<HeaderValue>) -> Option<Duration> {
         let now = Date::now_utc().as_timestamp_ms();
 
         let mut retry_after_ms = None;
@@ -283,19 +397,167 @@ impl HttpClient {
             }
         }
 
-        if let Some(retry_after) = retry_after_ms {
-            let duration = Duration::from_millis(retry_after as u64);
-            if duration <= RATE_LIMIT_MAX_WAIT {
-                return Some(duration);
-            } else {
-                debug!(
-                    "Waiting {} seconds would exceed {} second limit",
-                    duration.as_secs(),
-                    RATE_LIMIT_MAX_WAIT.as_secs()
-                );
+        // a moment in the past means no waiting at all
+        retry_after_ms.map(|retry_after| Duration::from_millis(retry_after.max(0) as u64))
+    }
+}
+
+/// How long to wait before the `tries`th retry of a request that failed without the service
+/// saying when to try again: exponentially longer for every try, with some jitter so clients
+/// that failed together don't retry together.
+pub fn backoff(tries: usize) -> Duration {
+    let exponent = tries.saturating_sub(1).min(16) as u32;
+    let delay = BACKOFF_BASE.saturating_mul(1 << exponent).min(BACKOFF_MAX);
+    let jitter = rand::thread_rng().gen_range(0.0..0.25);
+    delay.mul_f64(1.0 + jitter).min(BACKOFF_MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::stand_in::{Reply, StandIn};
+
+    fn rate_limited(status: StatusCode, seconds: u64) -> Reply {
+        Reply::new(status).header("Retry-After", seconds)
+    }
+
+    async fn get(url: &str) -> Result<Bytes, Error> {
+        let request = Request::get(url).body(Bytes::new()).unwrap();
+        HttpClient::new(None).request_body(request).await
+    }
+
+    #[tokio::test]
+    async fn waits_out_short_rate_limits() {
+        let stand_in = StandIn::replies(vec![
+            rate_limited(StatusCode::TOO_MANY_REQUESTS, 0),
+            rate_limited(StatusCode::SERVICE_UNAVAILABLE, 0),
+            Reply::ok("hello"),
+        ])
+        .await;
+
+        assert_eq!(get(stand_in.url()).await.unwrap(), Bytes::from("hello"));
+        assert_eq!(stand_in.served(), 3);
+    }
+
+    #[tokio::test]
+    async fn leaves_long_rate_limits_to_the_caller() {
+        let stand_in =
+            StandIn::replies(vec![rate_limited(StatusCode::SERVICE_UNAVAILABLE, 120)]).await;
+
+        let err = get(stand_in.url()).await.unwrap_err();
+        assert_eq!(err.kind, crate::error::ErrorKind::Unavailable);
+        assert!(err.is_retryable());
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(120)));
+        assert_eq!(stand_in.served(), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_repeated_rate_limits() {
+        let stand_in = StandIn::replies(vec![rate_limited(StatusCode::TOO_MANY_REQUESTS, 0)]).await;
+
+        let err = get(stand_in.url()).await.unwrap_err();
+        assert!(err.is_retryable());
+        assert_eq!(err.retry_after(), Some(Duration::ZERO));
+        assert_eq!(stand_in.served(), RATE_LIMIT_MAX_TRIES);
+    }
+
+    #[tokio::test]
+    async fn permanent_failures_are_not_retryable() {
+        let stand_in = StandIn::replies(vec![rate_limited(StatusCode::NOT_FOUND, 5)]).await;
+        let err = get(stand_in.url()).await.unwrap_err();
+        assert!(!err.is_retryable());
+        assert_eq!(err.retry_after(), None);
+
+        let stand_in =
+            StandIn::replies(vec![Reply::new(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS)]).await;
+        let err = get(stand_in.url()).await.unwrap_err();
+        assert_eq!(err.kind, crate::error::ErrorKind::Unavailable);
+        assert!(!err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn throttled_requests_are_told_apart_from_rate_limited_ones() {
+        let stand_in = StandIn::replies(vec![rate_limited(StatusCode::TOO_MANY_REQUESTS, 5)]).await;
+        let client = HttpClient::new(None).with_rate_limits(&RateLimitConfig {
+            per_host: crate::config::RequestRate::new(
+                1.try_into().unwrap(),
+                Duration::from_secs(60),
+            ),
+            ..Default::default()
+        });
+        let request = || Request::get(stand_in.url()).body(Bytes::new()).unwrap();
+
+        let response = client.request_fut(request()).unwrap().await.unwrap();
+        let err = HttpClient::status_error(response.status(), response.headers());
+        assert!(!err.is_throttled());
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(5)));
+
+        let err = client.request_fut(request()).unwrap_err();
+        assert!(err.is_throttled());
+        assert!(err.is_retryable());
+        assert!(err.retry_after().unwrap() > Duration::from_secs(50));
+        assert_eq!(stand_in.served(), 1);
+    }
+
+    #[test]
+    fn backoff_grows_up_to_a_limit() {
+        assert!(backoff(1) >= BACKOFF_BASE && backoff(1) < BACKOFF_BASE * 2);
+        assert!(backoff(3) >= BACKOFF_BASE * 4);
+        assert_eq!(backoff(100), BACKOFF_MAX);
+    }
+
+    // A SOCKS5 proxy without authentication that connects every client to `target`
+    async fn socks_proxy(target: String) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("socks5h://{}", listener.local_addr().unwrap())).unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let mut greeting = [0; 3];
+                client.read_exact(&mut greeting).await.unwrap();
+                client.write_all(&[5, 0]).await.unwrap();
+
+                let mut request = [0; 5];
+                client.read_exact(&mut request).await.unwrap();
+                let mut host_and_port = vec![0; request[4] as usize + 2];
+                client.read_exact(&mut host_and_port).await.unwrap();
+                client
+                    .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+                    .await
+                    .unwrap();
+
+                let mut server = tokio::net::TcpStream::connect(&target).await.unwrap();
+                tokio::spawn(async move {
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+                });
             }
-        }
+        });
+
+        url
+    }
 
-        None
+    #[tokio::test]
+    async fn requests_go_through_socks_proxies() {
+        let stand_in = StandIn::replies(vec![Reply::ok("hello")]).await;
+        let target = stand_in.url().trim_start_matches("http://").to_owned();
+        let proxy = socks_proxy(target).await;
+
+        // the host name only resolves on the proxy's side
+        let request = Request::get("http://stand-in.invalid/")
+            .body(Bytes::new())
+            .unwrap();
+        let response = HttpClient::new(Some(&proxy))
+            .request_body(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response, Bytes::from("hello"));
+        assert_eq!(stand_in.served(), 1);
     }
 }
@@ -27,11 +27,15 @@ pub mod login5;
 pub mod mercury;
 pub mod packet;
 mod proxytunnel;
+pub mod rate_limit;
 pub mod session;
 mod socket;
 #[allow(dead_code)]
 pub mod spclient;
 pub mod spotify_id;
+#[cfg(any(test, feature = "test-util"))]
+#[doc(hidden)]
+pub mod stand_in;
 pub mod token;
 #[doc(hidden)]
 pub mod util;
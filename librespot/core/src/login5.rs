@@ -158,6 +158,11 @@ impl Login5Manager {
         Ok((auth_token, token_response.stored_credential))
     }
 
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn set_auth_token(&self, token: Token) {
+        self.lock(|inner| inner.auth_token = Some(token));
+    }
+
     /// Retrieve the access_token via login5
     ///
     /// This request will only work when the store credentials match the client-id. Meaning that
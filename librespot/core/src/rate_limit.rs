@@ -0,0 +1,334 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
+
+use governor::{
+    clock::MonotonicClock,
+    middleware::NoOpMiddleware,
+    state::{InMemoryState, NotKeyed},
+    NotUntil, Quota, RateLimiter,
+};
+use parking_lot::Mutex;
+
+use crate::config::{RateLimitConfig, RequestRate};
+
+type DirectLimiter = RateLimiter<NotKeyed, InMemoryState, MonotonicClock, NoOpMiddleware>;
+
+// while the DashMap variant is more performant, our level of concurrency
+// is pretty low so we can save pulling in that extra dependency
+type KeyedLimiter =
+    RateLimiter<String, Mutex<HashMap<String, InMemoryState>>, MonotonicClock, NoOpMiddleware>;
+
+/// A budget that a request counts against on top of the one of its host. The HTTP client
+/// takes it from the extensions of the request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Budget {
+    Metadata,
+    Cdn,
+}
+
+/// One of the limits in [`RateLimits`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Limit {
+    Host(String),
+    Budget(Budget),
+    Bytes,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Host(host) => write!(f, "requests to {host}"),
+            Self::Budget(Budget::Metadata) => write!(f, "metadata requests"),
+            Self::Budget(Budget::Cdn) => write!(f, "CDN requests"),
+            Self::Bytes => write!(f, "received bytes"),
+        }
+    }
+}
+
+fn quota(rate: &RequestRate) -> Option<Quota> {
+    let period = rate.interval / rate.calls.get();
+    Quota::with_period(period).map(|quota| quota.allow_burst(rate.calls))
+}
+
+fn direct(rate: &RequestRate) -> Option<DirectLimiter> {
+    quota(rate).map(RateLimiter::direct)
+}
+
+/// The rate limits of the HTTP client, as set in [`RateLimitConfig`].
+///
+/// For rate limiting we cannot *just* depend on Spotify sending us HTTP/429 Retry-After
+/// headers. For example, when there is a service interruption and HTTP/500 is returned, we
+/// don't want to DoS the Spotify infrastructure.
+pub struct RateLimits {
+    per_host: Option<KeyedLimiter>,
+    hosts: HashMap<String, DirectLimiter>,
+    metadata: Option<DirectLimiter>,
+    cdn: Option<DirectLimiter>,
+    bytes: Option<(DirectLimiter, NonZeroU32)>,
+
+    // until when requests held back by each limit have to wait
+    waits: Mutex<HashMap<Limit, Instant>>,
+}
+
+impl RateLimits {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let hosts = config
+            .hosts
+            .iter()
+            .filter_map(|(host, rate)| Some((host.clone(), direct(rate)?)))
+            .collect();
+
+        let bytes = config.max_bytes_per_second.map(|bytes| {
+            let limiter = RateLimiter::direct(Quota::per_second(bytes));
+            (limiter, bytes)
+        });
+
+        Self {
+            per_host: quota(&config.per_host).map(RateLimiter::keyed),
+            hosts,
+            metadata: config.metadata.as_ref().and_then(direct),
+            cdn: config.cdn.as_ref().and_then(direct),
+            bytes,
+            waits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a request to `host` from its budgets, or returns how long to wait until it can be
+    /// made.
+    pub fn check(&self, host: &str, budget: Option<Budget>) -> Result<(), Duration> {
+        let key = self.host_key(host);
+        let host_limit = Limit::Host(key.clone());
+        let budget_limit = budget.map(Limit::Budget);
+
+        // The limiters can't be asked without taking from them, so a request that a limit is
+        // known to hold back is failed before taking from any of them. Otherwise requests held
+        // back by a budget would keep using up the one of their host.
+        let wait = budget_limit
+            .iter()
+            .map(|limit| self.wait_time(limit))
+            .fold(self.wait_time(&host_limit), Duration::max);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        self.check_limit(&host_limit, || self.check_host(&key))?;
+
+        if let Some(budget) = budget {
+            self.check_limit(&Limit::Budget(budget), || self.check_budget(budget))?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits until a request to `host` can be made, and takes it from its budgets.
+    pub async fn until_ready(&self, host: &str, budget: Option<Budget>) {
+        let key = self.host_key(host);
+        let limit = Limit::Host(key.clone());
+        while let Err(wait) = self.check_limit(&limit, || self.check_host(&key)) {
+            tokio::time::sleep(wait).await;
+        }
+
+        if let Some(budget) = budget {
+            let limit = Limit::Budget(budget);
+            while let Err(wait) = self.check_limit(&limit, || self.check_budget(budget)) {
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+
+    /// Waits until receiving `bytes` more stays within `max_bytes_per_second`.
+    pub async fn received(&self, bytes: usize) {
+        let Some((limiter, burst)) = &self.bytes else {
+            return;
+        };
+
+        // more than a second's worth has to be taken in parts
+        let mut remaining = bytes;
+        while let Some(part) = NonZeroU32::new(remaining.min(burst.get() as usize) as u32) {
+            let check = || match limiter.check_n(part) {
+                Ok(result) => result,
+                Err(_) => unreachable!("parts should not be larger than the burst"),
+            };
+            while let Err(wait) = self.check_limit(&Limit::Bytes, check) {
+                tokio::time::sleep(wait).await;
+            }
+            remaining -= part.get() as usize;
+        }
+    }
+
+    /// How long requests held back by `limit` are currently waiting, zero if none are.
+    pub fn wait_time(&self, limit: &Limit) -> Duration {
+        self.waits
+            .lock()
+            .get(limit)
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .unwrap_or_default()
+    }
+
+    /// The limits that are currently holding back requests, and for how long.
+    pub fn wait_times(&self) -> Vec<(Limit, Duration)> {
+        let now = Instant::now();
+        let mut waits = self.waits.lock();
+        waits.retain(|_, until| *until > now);
+        waits
+            .iter()
+            .map(|(limit, until)| (limit.clone(), *until - now))
+            .collect()
+    }
+
+    // The key of the limiter requests to `host` are held to: the host itself when it has a
+    // budget of its own, otherwise its domain.
+    fn host_key(&self, host: &str) -> String {
+        if self.hosts.contains_key(host) {
+            return host.to_owned();
+        }
+
+        // strip the prefix from *.domain.tld (assume rate limit is per domain, not subdomain)
+        let parts: Vec<&str> = host.split('.').collect();
+        let n = parts.len().saturating_sub(2);
+        parts[n..].join(".")
+    }
+
+    fn check_host(&self, key: &str) -> Result<(), NotUntil<Instant>> {
+        match self.hosts.get(key) {
+            Some(limiter) => limiter.check(),
+            None => match &self.per_host {
+                Some(limiter) => limiter.check_key(&key.to_owned()),
+                None => Ok(()),
+            },
+        }
+    }
+
+    fn check_budget(&self, budget: Budget) -> Result<(), NotUntil<Instant>> {
+        let limiter = match budget {
+            Budget::Metadata => &self.metadata,
+            Budget::Cdn => &self.cdn,
+        };
+        limiter.as_ref().map_or(Ok(()), |limiter| limiter.check())
+    }
+
+    // Runs `check`, and keeps track of how long `limit` holds back requests
+    fn check_limit<F>(&self, limit: &Limit, check: F) -> Result<(), Duration>
+    where
+        F: FnOnce() -> Result<(), NotUntil<Instant>>,
+    {
+        let now = Instant::now();
+        match check() {
+            Ok(()) => Ok(()),
+            Err(not_until) => {
+                let wait = not_until.wait_time_from(now);
+                let mut waits = self.waits.lock();
+                let until = waits.entry(limit.clone()).or_insert(now);
+                *until = (*until).max(now + wait);
+                Err(wait)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rate(calls: u32, interval: Duration) -> RequestRate {
+        RequestRate::new(NonZeroU32::new(calls).unwrap(), interval)
+    }
+
+    #[test]
+    fn subdomains_share_a_budget() {
+        let limits = RateLimits::new(&RateLimitConfig {
+            per_host: rate(2, Duration::from_secs(60)),
+            ..Default::default()
+        });
+
+        assert!(limits.check("api.spotify.com", None).is_ok());
+        assert!(limits.check("spclient.wg.spotify.com", None).is_ok());
+        let wait = limits.check("api.spotify.com", None).unwrap_err();
+        assert!(wait > Duration::from_secs(20));
+
+        assert!(limits.check("audio4-fa.scdn.co", None).is_ok());
+        assert!(limits.wait_time(&Limit::Host("spotify.com".to_owned())) > Duration::ZERO);
+        assert_eq!(
+            limits.wait_time(&Limit::Host("scdn.co".to_owned())),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn hosts_can_have_their_own_budget() {
+        let limits = RateLimits::new(&RateLimitConfig {
+            per_host: rate(1, Duration::from_secs(60)),
+            hosts: HashMap::from([(
+                "api.spotify.com".to_owned(),
+                rate(3, Duration::from_secs(60)),
+            )]),
+            ..Default::default()
+        });
+
+        for _ in 0..3 {
+            assert!(limits.check("api.spotify.com", None).is_ok());
+        }
+        assert!(limits.check("api.spotify.com", None).is_err());
+
+        assert!(limits.check("spclient.wg.spotify.com", None).is_ok());
+        assert!(limits.check("spclient.wg.spotify.com", None).is_err());
+    }
+
+    #[test]
+    fn budgets_apply_across_hosts() {
+        let limits = RateLimits::new(&RateLimitConfig {
+            cdn: Some(rate(1, Duration::from_secs(60))),
+            ..Default::default()
+        });
+
+        assert!(limits.check("audio4-fa.scdn.co", Some(Budget::Cdn)).is_ok());
+        assert!(limits
+            .check("audio-ak.spotifycdn.com", Some(Budget::Cdn))
+            .is_err());
+        assert!(limits
+            .check("api.spotify.com", Some(Budget::Metadata))
+            .is_ok());
+
+        let waits = limits.wait_times();
+        assert_eq!(waits.len(), 1);
+        assert_eq!(waits[0].0, Limit::Budget(Budget::Cdn));
+    }
+
+    #[test]
+    fn requests_held_back_by_a_budget_keep_the_host_budget() {
+        let limits = RateLimits::new(&RateLimitConfig {
+            per_host: rate(3, Duration::from_secs(60)),
+            cdn: Some(rate(1, Duration::from_secs(60))),
+            ..Default::default()
+        });
+
+        assert!(limits.check("audio4-fa.scdn.co", Some(Budget::Cdn)).is_ok());
+        for _ in 0..10 {
+            assert!(limits
+                .check("audio4-fa.scdn.co", Some(Budget::Cdn))
+                .is_err());
+        }
+
+        // only the request that found the budget used up took from the host
+        assert!(limits.check("audio4-fa.scdn.co", None).is_ok());
+        assert!(limits.check("audio4-fa.scdn.co", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn received_bytes_are_paced() {
+        let limits = RateLimits::new(&RateLimitConfig {
+            max_bytes_per_second: NonZeroU32::new(1000),
+            ..Default::default()
+        });
+
+        // the first second's worth goes at once, the rest has to wait
+        let start = Instant::now();
+        limits.received(1500).await;
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        assert!(limits.wait_time(&Limit::Bytes) <= Duration::from_millis(100));
+    }
+}
@@ -125,7 +125,8 @@ pub struct Session(Arc<SessionInternal>);
 
 impl Session {
     pub fn new(config: SessionConfig, cache: Option<Cache>) -> Self {
-        let http_client = HttpClient::new(config.proxy.as_ref());
+        let http_client =
+            HttpClient::new(config.proxy.as_ref()).with_rate_limits(&config.rate_limits);
 
         debug!("new Session");
 
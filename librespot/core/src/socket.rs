@@ -9,17 +9,13 @@ pub async fn connect(host: &str, port: u16, proxy: Option<&Url>) -> io::Result<T
     let socket = if let Some(proxy_url) = proxy {
         info!("Using proxy \"{}\"", proxy_url);
 
-        let socket_addr = proxy_url.socket_addrs(|| None).and_then(|addrs| {
-            addrs.into_iter().next().ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "Can't resolve proxy server address",
-                )
-            })
-        })?;
-        let socket = TcpStream::connect(&socket_addr).await?;
+        let socket = connect_proxy(proxy_url).await?;
 
-        proxytunnel::proxy_connect(socket, host, &port.to_string()).await?
+        if proxytunnel::is_socks(proxy_url) {
+            proxytunnel::socks_connect(socket, proxy_url, host, port).await?
+        } else {
+            proxytunnel::proxy_connect(socket, host, &port.to_string()).await?
+        }
     } else {
         let socket_addr = (host, port).to_socket_addrs()?.next().ok_or_else(|| {
             io::Error::new(
@@ -32,3 +28,23 @@ pub async fn connect(host: &str, port: u16, proxy: Option<&Url>) -> io::Result<T
     };
     Ok(socket)
 }
+
+/// Opens a connection to the proxy server itself.
+pub async fn connect_proxy(proxy_url: &Url) -> io::Result<TcpStream> {
+    // SOCKS proxies don't have a well known port in `Url`
+    let default_port = if proxytunnel::is_socks(proxy_url) {
+        Some(1080)
+    } else {
+        None
+    };
+
+    let socket_addr = proxy_url.socket_addrs(|| default_port).and_then(|addrs| {
+        addrs.into_iter().next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "Can't resolve proxy server address",
+            )
+        })
+    })?;
+    TcpStream::connect(&socket_addr).await
+}
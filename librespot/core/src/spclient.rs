@@ -9,16 +9,19 @@ use crate::{
     cdn_url::CdnUrl,
     config::SessionConfig,
     error::ErrorKind,
+    http_client::{self, RATE_LIMIT_MAX_WAIT},
     protocol::{
         autoplay_context_request::AutoplayContextRequest,
         clienttoken_http::{
             ChallengeAnswer, ChallengeType, ClientTokenRequest, ClientTokenRequestType,
             ClientTokenResponse, ClientTokenResponseType,
         },
+        collection2v2::PageRequest,
         connect::PutStateRequest,
         context::Context,
         extended_metadata::BatchedEntityRequest,
     },
+    rate_limit::Budget,
     token::Token,
     util,
     version::spotify_semantic_version,
@@ -43,6 +46,8 @@ component! {
         accesspoint: Option<SocketAddress> = None,
         strategy: RequestStrategy = RequestStrategy::default(),
         client_token: Option<Token> = None,
+        // where requests go instead of the access point, like a stand-in in tests
+        base_url: Option<String> = None,
     }
 }
 
@@ -126,10 +131,24 @@ impl SpClient {
     }
 
     pub async fn base_url(&self) -> Result<String, Error> {
+        if let Some(url) = self.lock(|inner| inner.base_url.clone()) {
+            return Ok(url);
+        }
+
         let ap = self.get_accesspoint().await?;
         Ok(format!("https://{}:{}", ap.0, ap.1))
     }
 
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn set_base_url(&self, url: &str) {
+        self.lock(|inner| inner.base_url = Some(url.to_owned()));
+    }
+
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn set_client_token(&self, token: Token) {
+        self.lock(|inner| inner.client_token = Some(token));
+    }
+
     async fn client_token_request<M: Message>(&self, message: &M) -> Result<Bytes, Error> {
         let body = message.write_to_bytes()?;
 
@@ -455,7 +474,8 @@ impl SpClient {
             // url.push_str(endpoint);
 
 			// workaround for spotify missing tracks bug, replaces above
-            let mut url = if endpoint.starts_with("/metadata") {
+            let overridden = self.lock(|inner| inner.base_url.is_some());
+            let mut url = if endpoint.starts_with("/metadata") && !overridden {
                 String::from("https://spclient.wg.spotify.com")
             } else {
                 self.base_url().await?
@@ -490,6 +510,7 @@ impl SpClient {
                 .method(method)
                 .uri(url)
                 .body(Bytes::copy_from_slice(body))?;
+            request.extensions_mut().insert(Budget::Metadata);
 
             // Reconnection logic: keep getting (cached) tokens because they might have expired.
             let token = self.session().login5().auth_token().await?;
@@ -530,18 +551,26 @@ impl SpClient {
             // Reconnection logic: drop the current access point if we are experiencing issues.
             // This will cause the next call to base_url() to resolve a new one.
             if let Err(ref network_error) = last_response {
-                match network_error.kind {
-                    ErrorKind::Unavailable | ErrorKind::DeadlineExceeded => {
-                        // Keep trying the current access point three times before dropping it.
-                        if tries % 3 == 0 {
-                            self.flush_accesspoint().await
-                        }
-                    }
-                    _ => break, // if we can't build the request now, then we won't ever
+                // if we can't build the request now, then we won't ever
+                if !network_error.is_retryable() {
+                    break;
+                }
+
+                // Keep trying the current access point three times before dropping it.
+                if network_error.kind != ErrorKind::ResourceExhausted && tries % 3 == 0 {
+                    self.flush_accesspoint().await
                 }
-            }
 
-            debug!("Error was: {:?}", last_response);
+                // Back off for as long as the service asked, unless that is so long that it
+                // is better left to the caller.
+                let delay = match network_error.retry_after() {
+                    Some(delay) if delay > RATE_LIMIT_MAX_WAIT => break,
+                    Some(delay) => delay,
+                    None => http_client::backoff(tries),
+                };
+                debug!("Error was: {network_error:?}, retrying in {delay:?}");
+                tokio::time::sleep(delay).await;
+            }
         }
 
         last_response
@@ -628,6 +657,23 @@ impl SpClient {
         self.request(&Method::GET, &endpoint, None, None).await
     }
 
+    /// Requests `length` items of a playlist, starting at item `from`.
+    pub async fn get_playlist_range(
+        &self,
+        playlist_id: &SpotifyId,
+        from: usize,
+        length: usize,
+    ) -> SpClientResult {
+        let endpoint = format!(
+            "/playlist/v2/playlist/{}?from={}&length={}",
+            playlist_id.to_base62()?,
+            from,
+            length
+        );
+
+        self.request(&Method::GET, &endpoint, None, None).await
+    }
+
     pub async fn get_user_profile(
         &self,
         username: &str,
@@ -723,6 +769,32 @@ impl SpClient {
             .await
     }
 
+    // Returns the `types` of results matching `query` as JSON, like "track" or "album", with
+    // up to `limit` results of each type starting at `offset`. No `types` returns every type.
+    pub async fn search(
+        &self,
+        query: &str,
+        types: &[&str],
+        limit: usize,
+        offset: usize,
+    ) -> SpClientResult {
+        let query: String = url::form_urlencoded::byte_serialize(query.as_bytes()).collect();
+        let session = self.session();
+
+        let mut endpoint = format!(
+            "/searchview/km/v4/search/{query}?entityVersion=2&limit={limit}&offset={offset}&catalogue={}&country={}&username={}",
+            session.get_user_attribute("catalogue").unwrap_or_default(),
+            session.country(),
+            session.username(),
+        );
+        if !types.is_empty() {
+            let _ = write!(endpoint, "&types={}", types.join(","));
+        }
+
+        self.request_as_json(&Method::GET, &endpoint, None, None)
+            .await
+    }
+
     // TODO: Seen-in-the-wild but unimplemented endpoints
     // - /presence-view/v1/buddylist
 
@@ -746,7 +818,16 @@ impl SpClient {
         offset: usize,
         length: usize,
     ) -> Result<IntoStream<ResponseFuture>, Error> {
-        let url = cdn_url.try_get_url()?;
+        self.stream_from_cdn_url(cdn_url.try_get_url()?, offset, length)
+    }
+
+    /// Like `stream_from_cdn`, from a particular URL of a `CdnUrl`.
+    pub fn stream_from_cdn_url(
+        &self,
+        url: &str,
+        offset: usize,
+        length: usize,
+    ) -> Result<IntoStream<ResponseFuture>, Error> {
         let req = Request::builder()
             .method(&Method::GET)
             .uri(url)
@@ -754,6 +835,7 @@ impl SpClient {
                 RANGE,
                 HeaderValue::from_str(&format!("bytes={}-{}", offset, offset + length - 1))?,
             )
+            .extension(Budget::Cdn)
             .body(Bytes::new())?;
 
         let stream = self.session().http_client().request_stream(req)?;
@@ -890,4 +972,35 @@ impl SpClient {
 
         self.request(&Method::GET, &endpoint, None, None).await
     }
+
+    // Known sets: collection (liked tracks and saved albums), artist (followed artists) and
+    // show (saved shows). Pass the `next_page_token` of a response to get the page after it.
+    pub async fn get_collection_page(
+        &self,
+        set: &str,
+        pagination_token: &str,
+        limit: usize,
+    ) -> SpClientResult {
+        let request = PageRequest {
+            username: self.session().username(),
+            set: set.to_owned(),
+            pagination_token: pagination_token.to_owned(),
+            limit: limit as i32,
+            ..Default::default()
+        };
+        let body = request.write_to_bytes()?;
+
+        let mut headers = HeaderMap::new();
+        let content_type = HeaderValue::from_static("application/vnd.collection-v2.spotify.proto");
+        headers.insert(CONTENT_TYPE, content_type.clone());
+        headers.insert(ACCEPT, content_type);
+
+        self.request(
+            &Method::POST,
+            "/collection/v2/paging",
+            Some(headers),
+            Some(&body),
+        )
+        .await
+    }
 }
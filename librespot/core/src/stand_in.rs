@@ -0,0 +1,230 @@
+//! A local HTTP server that answers with canned replies, so that requests can be tested end to
+//! end without reaching Spotify. Only built for tests, or with the `test-util` feature.
+
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use hyper::StatusCode;
+use parking_lot::Mutex;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{spotify_id::SpotifyItemType, token::Token, Session, SessionConfig, SpotifyId};
+
+/// The id of the `n`th made-up track, for tests that need many tracks that differ.
+pub fn track_id(n: usize) -> SpotifyId {
+    SpotifyId {
+        id: n as u128 + 1,
+        item_type: SpotifyItemType::Track,
+    }
+}
+
+/// A request the stand-in received.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+
+    /// The path and query, like `/search?q=rick`.
+    pub target: String,
+    pub body: Bytes,
+}
+
+impl Request {
+    /// The target without the query.
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    /// The value of the query parameter `name`, if there is one.
+    pub fn query(&self, name: &str) -> Option<String> {
+        let (_, query) = self.target.split_once('?')?;
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    /// The value of the query parameter `name`, if there is one and it parses as a `T`.
+    pub fn query_as<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.query(name)?.parse().ok()
+    }
+}
+
+/// What the stand-in answers a request with.
+#[derive(Clone, Debug)]
+pub struct Reply {
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+}
+
+impl Reply {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    pub fn ok(body: impl Into<Bytes>) -> Self {
+        Self::new(StatusCode::OK).body(body)
+    }
+
+    pub fn header(mut self, name: &str, value: impl ToString) -> Self {
+        self.headers.push((name.to_owned(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+/// A local HTTP server that answers every request with what a function returns for it.
+pub struct StandIn {
+    url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl StandIn {
+    pub async fn new<F>(respond: F) -> Self
+    where
+        F: Fn(&Request) -> Reply + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let Some(request) = read_request(&mut socket).await else {
+                    continue;
+                };
+                let reply = respond(&request);
+                received.lock().push(request);
+
+                let _ = write_reply(&mut socket, reply).await;
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    /// Answers the requests with `replies` in order, repeating the last one.
+    pub async fn replies(replies: Vec<Reply>) -> Self {
+        let served = AtomicUsize::new(0);
+        Self::new(move |_| {
+            let n = served.fetch_add(1, Ordering::SeqCst);
+            replies[n.min(replies.len() - 1)].clone()
+        })
+        .await
+    }
+
+    /// Answers requests for a page with `serve(Some(page))`, where `page` is what `page_of` reads
+    /// from the request, and all others with `serve(None)`.
+    pub async fn paged<K, P, F>(page_of: P, serve: F) -> Self
+    where
+        P: Fn(&Request) -> Option<K> + Send + Sync + 'static,
+        F: Fn(Option<K>) -> Reply + Send + Sync + 'static,
+    {
+        Self::new(move |request| serve(page_of(request))).await
+    }
+
+    /// The address of the stand-in, without a trailing slash.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The requests answered so far, oldest first.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().clone()
+    }
+
+    pub fn served(&self) -> usize {
+        self.requests.lock().len()
+    }
+
+    /// A session that is logged in as far as `SpClient` can tell, and that sends all of its
+    /// `SpClient` requests to the stand-in.
+    pub fn session(&self) -> Session {
+        let session = Session::new(SessionConfig::default(), None);
+        session.set_auth_data(b"stand-in");
+        session.login5().set_auth_token(token());
+        session.spclient().set_client_token(token());
+        session.spclient().set_base_url(&self.url);
+        session
+    }
+}
+
+fn token() -> Token {
+    Token {
+        access_token: "stand-in".to_owned(),
+        expires_in: Duration::from_secs(3600),
+        token_type: "Bearer".to_owned(),
+        scopes: Vec::new(),
+        timestamp: Instant::now(),
+    }
+}
+
+async fn read_request(socket: &mut TcpStream) -> Option<Request> {
+    let mut data = Vec::new();
+    let mut buffer = [0; 4096];
+
+    loop {
+        let read = socket.read(&mut buffer).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        data.extend_from_slice(&buffer[..read]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut request = httparse::Request::new(&mut headers);
+        let httparse::Status::Complete(head) = request.parse(&data).ok()? else {
+            continue;
+        };
+
+        let length = request
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case("content-length"))
+            .and_then(|header| std::str::from_utf8(header.value).ok()?.parse().ok())
+            .unwrap_or(0);
+
+        if data.len() >= head + length {
+            return Some(Request {
+                method: request.method?.to_owned(),
+                target: request.path?.to_owned(),
+                body: Bytes::copy_from_slice(&data[head..head + length]),
+            });
+        }
+    }
+}
+
+async fn write_reply(socket: &mut TcpStream, reply: Reply) -> std::io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        reply.status.as_u16(),
+        reply.status.canonical_reason().unwrap_or_default()
+    );
+    for (name, value) in &reply.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        reply.body.len()
+    ));
+
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(&reply.body).await
+}
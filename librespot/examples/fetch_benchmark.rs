@@ -0,0 +1,197 @@
+//! Compares the wall-clock time per track of the default and the bulk `AudioFetchParams`
+//! against a local stand-in for the CDN that adds latency to every request.
+//!
+//! Fetch parameters can only be set once per process, so without arguments this re-runs
+//! itself once for every profile.
+
+use std::{
+    env,
+    io::{self, Read},
+    process::{exit, Command},
+    time::{Duration, Instant},
+};
+
+use librespot::{
+    audio::{AudioFetchParams, AudioFile},
+    core::{
+        cdn_url::{CdnUrl, MaybeExpiringUrl, MaybeExpiringUrls},
+        config::SessionConfig,
+        session::Session,
+        FileId,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const PROFILES: [&str; 2] = ["default", "bulk"];
+
+const TRACKS: usize = 3;
+// about four minutes of 320 kbit/s Ogg Vorbis
+const FILE_SIZE: usize = 10 * 1024 * 1024;
+const BYTES_PER_SECOND: usize = 40 * 1024;
+
+const LATENCY: Duration = Duration::from_millis(100);
+const BANDWIDTH: usize = 16 * 1024 * 1024;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+fn parse_range(request: &str) -> Option<(usize, usize)> {
+    let value = request.lines().find_map(|line| {
+        line.to_ascii_lowercase()
+            .strip_prefix("range: bytes=")
+            .map(String::from)
+    })?;
+    let (start, end) = value.trim().split_once('-')?;
+    let start = start.parse().ok()?;
+    let end = end.parse::<usize>().ok()?.min(FILE_SIZE - 1);
+    Some((start, end))
+}
+
+async fn serve_connection(mut socket: TcpStream) -> io::Result<()> {
+    let chunk = vec![0u8; CHUNK_SIZE];
+    let mut buffer = Vec::new();
+
+    loop {
+        let mut read = [0u8; 1024];
+        let n = socket.read(&mut read).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&read[..n]);
+
+        let Some(header_end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") else {
+            continue;
+        };
+        let request = String::from_utf8_lossy(&buffer[..header_end]).into_owned();
+        buffer.drain(..header_end + 4);
+
+        let Some((start, end)) = parse_range(&request) else {
+            socket
+                .write_all(b"HTTP/1.1 416 Range Not Satisfiable\r\ncontent-length: 0\r\n\r\n")
+                .await?;
+            continue;
+        };
+
+        tokio::time::sleep(LATENCY).await;
+
+        let length = end + 1 - start;
+        let header = format!(
+            "HTTP/1.1 206 Partial Content\r\ncontent-range: bytes {start}-{end}/{FILE_SIZE}\r\ncontent-length: {length}\r\n\r\n"
+        );
+        socket.write_all(header.as_bytes()).await?;
+
+        let mut remaining = length;
+        while remaining > 0 {
+            let n = remaining.min(CHUNK_SIZE);
+            socket.write_all(&chunk[..n]).await?;
+            tokio::time::sleep(Duration::from_secs_f64(n as f64 / BANDWIDTH as f64)).await;
+            remaining -= n;
+        }
+    }
+}
+
+async fn mock_cdn() -> io::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/audio", listener.local_addr()?);
+
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(serve_connection(socket));
+        }
+    });
+
+    Ok(url)
+}
+
+async fn run_profile(profile: &str) {
+    let params = match profile {
+        "default" => AudioFetchParams::default(),
+        "bulk" => AudioFetchParams::bulk(),
+        _ => {
+            eprintln!("PROFILE should be one of: {}", PROFILES.join(", "));
+            exit(1);
+        }
+    };
+    let _ = AudioFetchParams::set(params);
+
+    let url = mock_cdn().await.unwrap_or_else(|e| {
+        eprintln!("Error starting mock CDN: {}", e);
+        exit(1);
+    });
+    let session = Session::new(SessionConfig::default(), None);
+
+    let mut total = Duration::ZERO;
+    for i in 0..TRACKS {
+        let file_id = FileId::from_raw(&[i as u8; 20]);
+        let urls = MaybeExpiringUrls(vec![MaybeExpiringUrl(url.clone(), None)]);
+        let cdn_url = CdnUrl::with_urls(file_id, urls);
+
+        let start = Instant::now();
+        let mut file = AudioFile::open_cdn_url(&session, cdn_url, BYTES_PER_SECOND)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Error opening file: {}", e);
+                exit(1);
+            });
+
+        let copied = tokio::task::spawn_blocking(move || {
+            if let Ok(controller) = file.get_stream_loader_controller() {
+                controller.set_stream_mode();
+            }
+            io::copy(&mut file.by_ref(), &mut io::sink())
+        })
+        .await;
+
+        match copied {
+            Ok(Ok(bytes)) if bytes as usize == FILE_SIZE => (),
+            Ok(Ok(bytes)) => {
+                eprintln!("Expected {} bytes, got {}", FILE_SIZE, bytes);
+                exit(1);
+            }
+            Ok(Err(e)) => {
+                eprintln!("Error reading file: {}", e);
+                exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error joining reader: {}", e);
+                exit(1);
+            }
+        }
+
+        let elapsed = start.elapsed();
+        total += elapsed;
+        println!(
+            "{:>8} track {}: {:>6} ms",
+            profile,
+            i + 1,
+            elapsed.as_millis()
+        );
+    }
+
+    println!(
+        "{:>8} average: {:>6} ms per track",
+        profile,
+        (total / TRACKS as u32).as_millis()
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let args: Vec<_> = env::args().collect();
+    match args.len() {
+        1 => {
+            for profile in PROFILES {
+                let status = Command::new(&args[0]).arg(profile).status();
+                if !matches!(status, Ok(status) if status.success()) {
+                    eprintln!("Benchmark for profile {} failed", profile);
+                    exit(1);
+                }
+            }
+        }
+        2 => run_profile(&args[1]).await,
+        _ => eprintln!("Usage: {} [PROFILE]", args[0]),
+    }
+}
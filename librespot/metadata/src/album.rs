@@ -20,6 +20,7 @@ use crate::{
 use librespot_core::{date::Date, Error, Session, SpotifyId};
 
 use librespot_protocol as protocol;
+use protocol::extension_kind::ExtensionKind;
 pub use protocol::metadata::album::Type as AlbumType;
 use protocol::metadata::Disc as DiscMessage;
 
@@ -73,6 +74,8 @@ impl Album {
 #[async_trait]
 impl Metadata for Album {
     type Message = protocol::metadata::Album;
+    const EXTENSION_KIND: Option<ExtensionKind> = Some(ExtensionKind::ALBUM_V4);
+    const CACHE: bool = true;
 
     async fn request(session: &Session, album_id: &SpotifyId) -> RequestResult {
         session.spclient().get_album_metadata(album_id).await
@@ -19,6 +19,7 @@ use crate::{
 use librespot_core::{Error, Session, SpotifyId};
 
 use librespot_protocol as protocol;
+use protocol::extension_kind::ExtensionKind;
 pub use protocol::metadata::artist_with_role::ArtistRole;
 
 use protocol::metadata::ActivityPeriod as ActivityPeriodMessage;
@@ -170,6 +171,8 @@ impl Artist {
 #[async_trait]
 impl Metadata for Artist {
     type Message = protocol::metadata::Artist;
+    const EXTENSION_KIND: Option<ExtensionKind> = Some(ExtensionKind::ARTIST_V4);
+    const CACHE: bool = true;
 
     async fn request(session: &Session, artist_id: &SpotifyId) -> RequestResult {
         session.spclient().get_artist_metadata(artist_id).await
@@ -79,6 +79,8 @@ impl AudioItem {
                     return Err(Error::unavailable(MetadataError::ExplicitContentFiltered));
                 }
 
+                let availability = track.available_for_user(&session.user_data());
+
                 let track_id = track.id;
                 let uri = track_id.to_uri()?;
                 let album = track.album.name;
@@ -99,16 +101,6 @@ impl AudioItem {
                     Some(track.alternatives)
                 };
 
-                let availability = if Date::now_utc() < track.earliest_live_timestamp {
-                    Err(UnavailabilityReason::Embargo)
-                } else {
-                    available_for_user(
-                        &session.user_data(),
-                        &track.availability,
-                        &track.restrictions,
-                    )
-                };
-
                 let popularity = track.popularity.clamp(0, 100) as u8;
                 let number = track.number.max(0) as u32;
                 let disc_number = track.disc_number.max(0) as u32;
@@ -266,7 +258,7 @@ fn available(availability: &Availabilities) -> AudioItemAvailability {
     Ok(())
 }
 
-fn available_for_user(
+pub(crate) fn available_for_user(
     user_data: &UserData,
     availability: &Availabilities,
     restrictions: &Restrictions,
@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    future::Future,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use bytes::Bytes;
+
+use librespot_core::{cache::FsSizeLimiter, spotify_id::SpotifyItemType, Error};
+
+use crate::request::RequestResult;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// An on-disk cache for metadata responses, so entities that were fetched before don't have to
+/// be requested again while they are fresh, and can still be used when the network is not
+/// available.
+///
+/// Entries are kept per entity URI. Playlist items are also kept per revision, as the items of
+/// a revision never change.
+pub struct MetadataCache {
+    location: PathBuf,
+    ttls: HashMap<SpotifyItemType, Duration>,
+    default_ttl: Duration,
+    size_limiter: Option<Arc<FsSizeLimiter>>,
+}
+
+static METADATA_CACHE: OnceLock<MetadataCache> = OnceLock::new();
+
+impl MetadataCache {
+    /// Creates a cache in `path`, removing the least recently used entries when it grows over
+    /// `size_limit` bytes.
+    pub fn new<P: AsRef<Path>>(path: P, size_limit: Option<u64>) -> Result<Self, Error> {
+        let location = path.as_ref().to_owned();
+        fs::create_dir_all(&location)?;
+
+        let size_limiter = size_limit
+            .map(|limit| FsSizeLimiter::new(&location, limit).map(Arc::new))
+            .transpose()?;
+
+        let ttls = HashMap::from([
+            (SpotifyItemType::Track, 30 * DAY),
+            (SpotifyItemType::Album, 30 * DAY),
+            (SpotifyItemType::Episode, 30 * DAY),
+            (SpotifyItemType::Artist, DAY),
+            (SpotifyItemType::Show, DAY),
+            (SpotifyItemType::Playlist, HOUR),
+        ]);
+
+        Ok(Self {
+            location,
+            ttls,
+            default_ttl: DAY,
+            size_limiter,
+        })
+    }
+
+    /// Sets how long entries of `item_type` are used before they are requested again.
+    pub fn with_ttl(mut self, item_type: SpotifyItemType, ttl: Duration) -> Self {
+        self.ttls.insert(item_type, ttl);
+        self
+    }
+
+    /// Makes `Metadata::get` and `Metadata::get_many` use the cache. It can only be set once.
+    pub fn set(cache: MetadataCache) -> Result<(), MetadataCache> {
+        METADATA_CACHE.set(cache)
+    }
+
+    pub fn get() -> Option<&'static MetadataCache> {
+        METADATA_CACHE.get()
+    }
+
+    pub fn ttl(&self, item_type: SpotifyItemType) -> Duration {
+        self.ttls
+            .get(&item_type)
+            .copied()
+            .unwrap_or(self.default_ttl)
+    }
+
+    // Entries are stored as <type>/<id>[/<part>...], keys are URIs with optional extra parts
+    fn entry_path(&self, key: &str) -> PathBuf {
+        let mut path = self.location.clone();
+        path.extend(key.split(':').skip(1));
+        path
+    }
+
+    /// Returns the entry for `key`, and whether it is younger than `ttl`.
+    pub fn load(&self, key: &str, ttl: Duration) -> Option<(Bytes, bool)> {
+        let path = self.entry_path(key);
+
+        let read = || -> io::Result<(Bytes, bool)> {
+            let mut file = File::open(&path)?;
+            let age = file.metadata()?.modified()?.elapsed().unwrap_or_default();
+
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            Ok((data.into(), age < ttl))
+        };
+
+        match read() {
+            Ok(entry) => {
+                if let Some(limiter) = &self.size_limiter {
+                    limiter.touch(&path);
+                }
+                Some(entry)
+            }
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Error reading metadata from cache: {}", e);
+                }
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, key: &str, data: &[u8]) {
+        let path = self.entry_path(key);
+
+        let write = || -> io::Result<()> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            // written to the side first, so readers never see half an entry
+            let partial = path.with_extension("partial");
+            File::create(&partial)?.write_all(data)?;
+            fs::rename(&partial, &path)
+        };
+
+        if let Err(e) = write() {
+            warn!("Cannot save metadata to cache: {}", e);
+            return;
+        }
+
+        if let Some(limiter) = &self.size_limiter {
+            limiter.add(&path, data.len() as u64);
+            if let Err(e) = limiter.prune() {
+                warn!("Cannot prune metadata cache: {}", e);
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &str) -> Result<(), Error> {
+        let path = self.entry_path(key);
+
+        fs::remove_file(&path)?;
+        if let Some(limiter) = &self.size_limiter {
+            limiter.remove(&path);
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the cached entry for `key` if it is fresh, otherwise makes `request` and caches
+/// its response. When the request fails with a transient error, a stale entry is returned
+/// instead of the error.
+///
+/// Entries expire after the TTL of `item_type`, or never without one.
+pub(crate) async fn cached<F>(
+    key: &str,
+    item_type: Option<SpotifyItemType>,
+    request: F,
+) -> RequestResult
+where
+    F: Future<Output = RequestResult>,
+{
+    let Some(cache) = MetadataCache::get() else {
+        return request.await;
+    };
+
+    let ttl = item_type.map_or(Duration::MAX, |item_type| cache.ttl(item_type));
+    let stale = match cache.load(key, ttl) {
+        Some((data, true)) => return Ok(data),
+        Some((data, false)) => Some(data),
+        None => None,
+    };
+
+    match request.await {
+        Ok(data) => {
+            cache.save(key, &data);
+            Ok(data)
+        }
+        // an outdated entry beats a service that is down, but not one that is gone
+        Err(e) => match stale {
+            Some(data) if e.is_retryable() => {
+                warn!("Using outdated metadata for {}: {}", key, e);
+                Ok(data)
+            }
+            _ => Err(e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_cache(name: &str) -> MetadataCache {
+        let path = std::env::temp_dir().join(format!("librespot-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        MetadataCache::new(path, Some(1000)).unwrap()
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let cache = temp_cache("metadata-cache-ttl");
+        let key = "spotify:track:4uLU6hMCjMI75M1A2tKUQC";
+
+        assert_eq!(cache.load(key, DAY), None);
+
+        cache.save(key, b"track");
+        assert_eq!(cache.load(key, DAY), Some((Bytes::from("track"), true)));
+        assert_eq!(
+            cache.load(key, Duration::ZERO),
+            Some((Bytes::from("track"), false))
+        );
+
+        cache.remove(key).unwrap();
+        assert_eq!(cache.load(key, DAY), None);
+    }
+
+    #[test]
+    fn test_entries_are_kept_per_part() {
+        let cache = temp_cache("metadata-cache-parts");
+
+        cache.save("spotify:playlist:37i9dQZF1DXcBWIGoYBM5M", b"head");
+        cache.save(
+            "spotify:playlist-items:37i9dQZF1DXcBWIGoYBM5M:ab01:0-100",
+            b"page",
+        );
+
+        assert!(cache
+            .entry_path("spotify:playlist-items:37i9dQZF1DXcBWIGoYBM5M:ab01:0-100")
+            .ends_with("playlist-items/37i9dQZF1DXcBWIGoYBM5M/ab01/0-100"));
+        let (head, _) = cache
+            .load("spotify:playlist:37i9dQZF1DXcBWIGoYBM5M", DAY)
+            .unwrap();
+        assert_eq!(head, Bytes::from("head"));
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let cache = temp_cache("metadata-cache-limit");
+
+        cache.save("spotify:track:a", &[0; 600]);
+        std::thread::sleep(Duration::from_millis(10));
+        cache.save("spotify:track:b", &[0; 600]);
+
+        // the oldest entry makes room for the newest
+        assert_eq!(cache.load("spotify:track:a", DAY), None);
+        assert!(cache.load("spotify:track:b", DAY).is_some());
+    }
+}
@@ -0,0 +1,241 @@
+use futures_util::{stream, Stream, TryStreamExt};
+use protobuf::Message;
+
+use librespot_core::{date::Date, Error, Session, SpotifyId};
+use librespot_protocol as protocol;
+
+use protocol::collection2v2::PageResponse;
+
+// The number of items requested at once
+const PAGE_SIZE: usize = 300;
+
+/// The parts of a user's library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CollectionKind {
+    LikedTracks,
+    SavedAlbums,
+    SavedShows,
+    FollowedArtists,
+}
+
+impl CollectionKind {
+    // Liked tracks and saved albums share a set, and are told apart by their URI
+    fn set(&self) -> &'static str {
+        match self {
+            Self::LikedTracks | Self::SavedAlbums => "collection",
+            Self::SavedShows => "show",
+            Self::FollowedArtists => "artist",
+        }
+    }
+
+    fn contains(&self, id: &SpotifyId) -> bool {
+        use librespot_core::spotify_id::SpotifyItemType::*;
+
+        matches!(
+            (self, id.item_type),
+            (Self::LikedTracks, Track)
+                | (Self::SavedAlbums, Album)
+                | (Self::SavedShows, Show)
+                | (Self::FollowedArtists, Artist)
+        )
+    }
+}
+
+/// Something in a user's library, and when it was added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionItem {
+    pub id: SpotifyId,
+    pub added_at: Date,
+}
+
+/// Streams everything of `kind` in the library of the logged in user, newest first, fetching
+/// it a page at a time.
+pub fn items(
+    session: &Session,
+    kind: CollectionKind,
+) -> impl Stream<Item = Result<CollectionItem, Error>> + Send + 'static {
+    let session = session.clone();
+
+    stream::try_unfold(Some(String::new()), move |token| {
+        let session = session.clone();
+        async move {
+            let Some(token) = token else {
+                return Ok::<_, Error>(None);
+            };
+
+            let response = session
+                .spclient()
+                .get_collection_page(kind.set(), &token, PAGE_SIZE)
+                .await?;
+            let page = PageResponse::parse_from_bytes(&response)?;
+
+            let items = page
+                .items
+                .iter()
+                .filter(|item| !item.is_removed)
+                .filter_map(|item| match SpotifyId::from_uri(&item.uri) {
+                    Ok(id) => Some((id, item.added_at)),
+                    Err(_) => {
+                        warn!("Skipping collection item with invalid uri {}", item.uri);
+                        None
+                    }
+                })
+                .filter(|(id, _)| kind.contains(id))
+                .map(|(id, added_at)| {
+                    Ok(CollectionItem {
+                        id,
+                        added_at: Date::from_timestamp_ms(added_at as i64 * 1000)?,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let next = Some(page.next_page_token).filter(|token| !token.is_empty());
+            Ok(Some((stream::iter(items), next)))
+        }
+    })
+    .try_flatten()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures_util::StreamExt;
+    use librespot_core::stand_in::{Reply, Request, StandIn};
+    use protocol::collection2v2::{CollectionItem as Item, PageRequest};
+
+    fn item(uri: &str, added_at: i32) -> Item {
+        Item {
+            uri: uri.to_owned(),
+            added_at,
+            ..Default::default()
+        }
+    }
+
+    fn page(items: Vec<Item>, next_page_token: &str) -> Reply {
+        let page = PageResponse {
+            items,
+            next_page_token: next_page_token.to_owned(),
+            ..Default::default()
+        };
+        Reply::ok(page.write_to_bytes().unwrap())
+    }
+
+    // The pagination token of a request for a page after the first
+    fn token(request: &Request) -> Option<String> {
+        let request = PageRequest::parse_from_bytes(&request.body).unwrap();
+        Some(request.pagination_token).filter(|token| !token.is_empty())
+    }
+
+    fn requests(stand_in: &StandIn) -> Vec<PageRequest> {
+        stand_in
+            .requests()
+            .iter()
+            .map(|request| {
+                assert_eq!(request.method, "POST");
+                assert!(request.target.starts_with("/collection/v2/paging"));
+                PageRequest::parse_from_bytes(&request.body).unwrap()
+            })
+            .collect()
+    }
+
+    const TRACK: &str = "spotify:track:4uLU6hMCjMI75M1A2tKUQC";
+    const OTHER_TRACK: &str = "spotify:track:7GhIk7Il098yCjg4BQjzvb";
+    const ALBUM: &str = "spotify:album:6N9PS4QXF1D0OWPk0Sxtb4";
+
+    #[tokio::test]
+    async fn fetches_every_page() {
+        let stand_in = StandIn::paged(token, |token| match token.as_deref() {
+            None => page(
+                vec![
+                    item(TRACK, 1_700_000_000),
+                    item(ALBUM, 1_690_000_000),
+                    item("spotify:track:invalid", 1_680_000_000),
+                ],
+                "second",
+            ),
+            Some("second") => page(
+                vec![
+                    Item {
+                        is_removed: true,
+                        ..item(TRACK, 1_600_000_000)
+                    },
+                    item(OTHER_TRACK, 1_500_000_000),
+                ],
+                "",
+            ),
+            Some(token) => panic!("unexpected pagination token {token}"),
+        })
+        .await;
+
+        let session = stand_in.session();
+        let items: Vec<_> = items(&session, CollectionKind::LikedTracks)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            items,
+            [
+                CollectionItem {
+                    id: SpotifyId::from_uri(TRACK).unwrap(),
+                    added_at: Date::from_timestamp_ms(1_700_000_000_000).unwrap(),
+                },
+                CollectionItem {
+                    id: SpotifyId::from_uri(OTHER_TRACK).unwrap(),
+                    added_at: Date::from_timestamp_ms(1_500_000_000_000).unwrap(),
+                },
+            ]
+        );
+
+        let requests = requests(&stand_in);
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].set, "collection");
+        assert_eq!(requests[0].limit, PAGE_SIZE as i32);
+        assert_eq!(requests[1].pagination_token, "second");
+    }
+
+    #[tokio::test]
+    async fn tells_saved_albums_apart_from_liked_tracks() {
+        let stand_in =
+            StandIn::paged(token, |_| page(vec![item(TRACK, 1), item(ALBUM, 2)], "")).await;
+
+        let session = stand_in.session();
+        let items: Vec<_> = items(&session, CollectionKind::SavedAlbums)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].id, SpotifyId::from_uri(ALBUM).unwrap());
+    }
+
+    #[tokio::test]
+    async fn an_empty_collection_has_no_items() {
+        let stand_in = StandIn::paged(token, |_| page(Vec::new(), "")).await;
+
+        let session = stand_in.session();
+        let items: Vec<_> = items(&session, CollectionKind::FollowedArtists)
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(items.is_empty());
+
+        let requests = requests(&stand_in);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].set, "artist");
+    }
+
+    #[tokio::test]
+    async fn fails_on_a_page_that_does_not_parse() {
+        let stand_in = StandIn::paged(token, |token| match token.as_deref() {
+            None => page(vec![item(TRACK, 1)], "second"),
+            _ => Reply::ok("not a page"),
+        })
+        .await;
+
+        let session = stand_in.session();
+        let items: Vec<_> = items(&session, CollectionKind::LikedTracks).collect().await;
+        assert_eq!(items.len(), 2);
+        assert!(items[0].is_ok());
+        assert!(items[1].is_err());
+    }
+}
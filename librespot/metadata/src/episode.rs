@@ -18,6 +18,7 @@ use crate::{
 use librespot_core::{date::Date, Error, Session, SpotifyId};
 
 use librespot_protocol as protocol;
+use protocol::extension_kind::ExtensionKind;
 pub use protocol::metadata::episode::EpisodeType;
 
 #[derive(Debug, Clone)]
@@ -56,6 +57,8 @@ impl_deref_wrapped!(Episodes, Vec<SpotifyId>);
 #[async_trait]
 impl Metadata for Episode {
     type Message = protocol::metadata::Episode;
+    const EXTENSION_KIND: Option<ExtensionKind> = Some(ExtensionKind::EPISODE_V4);
+    const CACHE: bool = true;
 
     async fn request(session: &Session, episode_id: &SpotifyId) -> RequestResult {
         session.spclient().get_episode_metadata(episode_id).await
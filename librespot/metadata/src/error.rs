@@ -11,4 +11,6 @@ pub enum MetadataError {
     InvalidDuration(i32),
     #[error("track is marked as explicit, which client setting forbids")]
     ExplicitContentFiltered,
+    #[error("asked for items from {0}, but got them from {1}")]
+    UnexpectedPosition(usize, i32),
 }
@@ -4,14 +4,22 @@ extern crate log;
 #[macro_use]
 extern crate async_trait;
 
+use std::collections::HashMap;
+
 use protobuf::Message;
 
-use librespot_core::{Error, Session, SpotifyId};
+use librespot_core::{http_client::RATE_LIMIT_MAX_WAIT, Error, Session, SpotifyId};
+use librespot_protocol::extension_kind::ExtensionKind;
+
+// The number of entities requested at once by `Metadata::get_many`
+const BATCH_SIZE: usize = 100;
 
 pub mod album;
 pub mod artist;
 pub mod audio;
 pub mod availability;
+pub mod cache;
+pub mod collection;
 pub mod content_rating;
 pub mod copyright;
 pub mod episode;
@@ -20,9 +28,11 @@ pub mod external_id;
 pub mod image;
 pub mod lyrics;
 pub mod playlist;
+pub mod radio;
 mod request;
 pub mod restriction;
 pub mod sale_period;
+pub mod search;
 pub mod show;
 pub mod track;
 mod util;
@@ -33,9 +43,12 @@ use request::RequestResult;
 
 pub use album::Album;
 pub use artist::Artist;
+pub use cache::MetadataCache;
 pub use episode::Episode;
 pub use lyrics::Lyrics;
 pub use playlist::Playlist;
+pub use radio::Station;
+pub use search::{SearchResults, SearchType};
 pub use show::Show;
 pub use track::Track;
 
@@ -46,13 +59,98 @@ pub trait Metadata: Send + Sized + 'static {
     // Request a protobuf
     async fn request(session: &Session, id: &SpotifyId) -> RequestResult;
 
+    // Whether responses can be kept in the `MetadataCache`, under the URI of the entity
+    const CACHE: bool = false;
+
     // Request a metadata struct
     async fn get(session: &Session, id: &SpotifyId) -> Result<Self, Error> {
-        let response = Self::request(session, id).await?;
-        let msg = Self::Message::parse_from_bytes(&response)?;
-        trace!("Received metadata: {:#?}", msg);
-        Self::parse(&msg, id)
+        let response = if Self::CACHE {
+            let key = id.to_uri()?;
+            cache::cached(&key, Some(id.item_type), Self::request(session, id)).await?
+        } else {
+            Self::request(session, id).await?
+        };
+        Self::parse_from_bytes(&response, id)
+    }
+
+    // The extended-metadata extension that carries `Message`, if there is one
+    const EXTENSION_KIND: Option<ExtensionKind> = None;
+
+    // Request many metadata structs, in the order of `ids`. Types with an `EXTENSION_KIND`
+    // are fetched in batches, and whatever a batch can't provide is requested on its own.
+    async fn get_many(session: &Session, ids: &[SpotifyId]) -> Vec<Result<Self, Error>> {
+        let mut output = Vec::with_capacity(ids.len());
+
+        let Some(kind) = Self::EXTENSION_KIND else {
+            for id in ids {
+                output.push(Self::get(session, id).await);
+            }
+            return output;
+        };
+
+        let cache = MetadataCache::get().filter(|_| Self::CACHE);
+
+        for batch in ids.chunks(BATCH_SIZE) {
+            // only what isn't cached or has gone stale is requested
+            let mut found = HashMap::new();
+            let mut missing = Vec::with_capacity(batch.len());
+            for id in batch {
+                let Ok(uri) = id.to_uri() else { continue };
+                match cache.and_then(|cache| cache.load(&uri, cache.ttl(id.item_type))) {
+                    Some((data, true)) => {
+                        found.insert(uri, data.to_vec());
+                    }
+                    _ => missing.push(*id),
+                }
+            }
+
+            if !missing.is_empty() {
+                let mut fetched = request::request_extended(session, kind, &missing).await;
+
+                // one by one would be rate limited just the same, so sit it out once instead,
+                // unless that takes longer than any other request would wait
+                let retry_after = fetched.as_ref().err().and_then(Error::retry_after);
+                if let Some(delay) = retry_after.filter(|delay| *delay <= RATE_LIMIT_MAX_WAIT) {
+                    warn!("Batched metadata request rate limited, retrying in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                    fetched = request::request_extended(session, kind, &missing).await;
+                }
+
+                match fetched {
+                    Ok(fetched) => {
+                        if let Some(cache) = cache {
+                            for (uri, data) in &fetched {
+                                cache.save(uri, data);
+                            }
+                        }
+                        found.extend(fetched);
+                    }
+                    Err(e) => warn!("Batched metadata request failed, requesting one by one: {e}"),
+                }
+            }
+
+            for id in batch {
+                let parsed = id
+                    .to_uri()
+                    .ok()
+                    .and_then(|uri| found.remove(&uri))
+                    .map(|data| Self::parse_from_bytes(&data, id));
+
+                output.push(match parsed {
+                    Some(Ok(item)) => Ok(item),
+                    _ => Self::get(session, id).await,
+                });
+            }
+        }
+
+        output
     }
 
     fn parse(msg: &Self::Message, _: &SpotifyId) -> Result<Self, Error>;
+
+    fn parse_from_bytes(data: &[u8], id: &SpotifyId) -> Result<Self, Error> {
+        let msg = Self::Message::parse_from_bytes(data)?;
+        trace!("Received metadata: {:#?}", msg);
+        Self::parse(&msg, id)
+    }
 }
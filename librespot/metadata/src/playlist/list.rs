@@ -3,14 +3,20 @@ use std::{
     ops::{Deref, DerefMut},
 };
 
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use protobuf::Message;
+
 use crate::{
+    cache,
     request::RequestResult,
     util::{impl_deref_wrapped, impl_from_repeated_copy, impl_try_from_repeated},
-    Metadata,
+    Metadata, MetadataError,
 };
 
 use super::{
-    attribute::PlaylistAttributes, diff::PlaylistDiff, item::PlaylistItemList,
+    attribute::PlaylistAttributes,
+    diff::PlaylistDiff,
+    item::{PlaylistItem, PlaylistItemList, PlaylistItems},
     permission::Capabilities,
 };
 
@@ -23,6 +29,9 @@ use librespot_core::{
 use librespot_protocol as protocol;
 use protocol::playlist4_external::GeoblockBlockingType as Geoblock;
 
+// The number of items requested at once when fetching the rest of a playlist
+const PAGE_SIZE: usize = 100;
+
 #[derive(Debug, Clone, Default)]
 pub struct Geoblocks(Vec<Geoblock>);
 
@@ -79,7 +88,7 @@ impl Playlist {
         let expected_length = self.length as usize;
         if length != expected_length {
             warn!(
-                "Got {} tracks, but the list should contain {} tracks.",
+                "Got {} tracks, but the list should contain {} tracks. Use `Playlist::items` to fetch all of them.",
                 length, expected_length,
             );
         }
@@ -87,6 +96,95 @@ impl Playlist {
         tracks
     }
 
+    /// Streams every item of the playlist, in order. The items the playlist was fetched with
+    /// come first; the rest are requested a page at a time until `length` items have been
+    /// yielded.
+    pub fn items(
+        &self,
+        session: &Session,
+    ) -> impl Stream<Item = Result<PlaylistItem, Error>> + Send + 'static {
+        let session = session.clone();
+        let id = self.id.inner_id;
+        let length = self.length.max(0) as usize;
+
+        let revision: String = self.revision.iter().map(|b| format!("{b:02x}")).collect();
+
+        let first = self.contents.items.0.clone();
+        let from = self.contents.position.max(0) as usize + first.len();
+
+        let rest = stream::try_unfold(from, move |from| {
+            let session = session.clone();
+            let revision = revision.clone();
+            async move {
+                if from >= length {
+                    return Ok::<_, Error>(None);
+                }
+
+                let page_len = PAGE_SIZE.min(length - from);
+                let page = Self::get_page(&session, &id, &revision, from, page_len).await?;
+                if page.is_empty() {
+                    warn!(
+                        "Playlist {} ended after {} of {} items.",
+                        id.to_uri()?,
+                        from,
+                        length
+                    );
+                    return Ok(None);
+                }
+
+                let next = from + page.len();
+                Ok(Some((stream::iter(page.0.into_iter().map(Ok)), next)))
+            }
+        })
+        .try_flatten();
+
+        stream::iter(first.into_iter().map(Ok)).chain(rest)
+    }
+
+    // The items of a revision don't change, so they can be cached for as long as there is room
+    async fn get_page(
+        session: &Session,
+        id: &SpotifyId,
+        revision: &str,
+        from: usize,
+        length: usize,
+    ) -> Result<PlaylistItems, Error> {
+        let key = format!(
+            "spotify:playlist-items:{}:{}:{}-{}",
+            id.to_base62()?,
+            revision,
+            from,
+            length
+        );
+        let ttl = revision.is_empty().then_some(id.item_type);
+
+        // a page from anywhere else would skip or repeat items, so it is neither used nor cached
+        let request = async {
+            let response = session
+                .spclient()
+                .get_playlist_range(id, from, length)
+                .await?;
+            Self::parse_page(&response, from)?;
+            Ok(response)
+        };
+        let response = cache::cached(&key, ttl, request).await?;
+        Self::parse_page(&response, from)
+    }
+
+    fn parse_page(response: &[u8], from: usize) -> Result<PlaylistItems, Error> {
+        let msg = <Self as Metadata>::Message::parse_from_bytes(response)?;
+        let contents = PlaylistItemList::try_from(msg.contents.get_or_default())?;
+
+        if contents.position as usize != from {
+            return Err(Error::unavailable(MetadataError::UnexpectedPosition(
+                from,
+                contents.position,
+            )));
+        }
+
+        Ok(contents.items)
+    }
+
     pub fn name(&self) -> &str {
         &self.attributes.name
     }
@@ -95,6 +193,7 @@ impl Playlist {
 #[async_trait]
 impl Metadata for Playlist {
     type Message = protocol::playlist4_external::SelectedListContent;
+    const CACHE: bool = true;
 
     async fn request(session: &Session, playlist_id: &SpotifyId) -> RequestResult {
         session.spclient().get_playlist(playlist_id).await
@@ -182,3 +281,108 @@ impl TryFrom<&<Playlist as Metadata>::Message> for SelectedListContent {
 
 impl_from_repeated_copy!(Geoblock, Geoblocks);
 impl_try_from_repeated!(Vec<u8>, Playlists);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use librespot_core::stand_in::{track_id, Reply, Request, StandIn};
+    use protobuf::MessageField;
+    use protocol::playlist4_external::{Item, ListItems};
+
+    const LENGTH: usize = 250;
+
+    // The playlist with the items from `from` to `to`, as if they started at `position`
+    fn page(position: usize, from: usize, to: usize) -> Reply {
+        let mut contents = ListItems::new();
+        contents.set_pos(position as i32);
+        contents.set_truncated(to < LENGTH);
+        for n in from..to {
+            let mut item = Item::new();
+            item.set_uri(track_id(n).to_uri().unwrap());
+            contents.items.push(item);
+        }
+
+        let mut playlist = <Playlist as Metadata>::Message::new();
+        playlist.set_revision(vec![0xab, 0xcd]);
+        playlist.set_length(LENGTH as i32);
+        playlist.contents = MessageField::some(contents);
+
+        Reply::ok(playlist.write_to_bytes().unwrap())
+    }
+
+    // The range of items asked for, as `(from, length)`
+    fn range(request: &Request) -> Option<(usize, usize)> {
+        Some((request.query_as("from")?, request.query_as("length")?))
+    }
+
+    async fn items(stand_in: &StandIn) -> Vec<Result<PlaylistItem, Error>> {
+        let session = stand_in.session();
+        let playlist = Playlist::get(
+            &session,
+            &SpotifyId::from_base62("37i9dQZF1DXcBWIGoYBM5M").unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(playlist.contents.items.len(), PAGE_SIZE);
+
+        playlist.items(&session).collect().await
+    }
+
+    fn requested_ranges(stand_in: &StandIn) -> Vec<(usize, usize)> {
+        stand_in.requests().iter().filter_map(range).collect()
+    }
+
+    #[tokio::test]
+    async fn fetches_the_rest_page_by_page() {
+        // the second page comes up short, so the third starts where it ended
+        let stand_in = StandIn::paged(range, |range| match range {
+            Some((from, length)) => {
+                let to = if from == 100 { 170 } else { from + length };
+                page(from, from, to.min(LENGTH))
+            }
+            None => page(0, 0, PAGE_SIZE),
+        })
+        .await;
+
+        let items: Vec<_> = items(&stand_in)
+            .await
+            .into_iter()
+            .map(|item| item.unwrap().id)
+            .collect();
+        assert_eq!(items, (0..LENGTH).map(track_id).collect::<Vec<_>>());
+
+        assert_eq!(requested_ranges(&stand_in), [(100, 100), (170, 80)]);
+    }
+
+    #[tokio::test]
+    async fn stops_at_a_page_from_elsewhere() {
+        // the page asked for from 200 starts at 190, which would repeat ten items
+        let stand_in = StandIn::paged(range, |range| match range {
+            Some((from, length)) => {
+                let position = if from == 200 { 190 } else { from };
+                page(position, position, (position + length).min(LENGTH))
+            }
+            None => page(0, 0, PAGE_SIZE),
+        })
+        .await;
+
+        let items = items(&stand_in).await;
+        assert_eq!(items.len(), 201);
+        assert!(items[..200].iter().all(Result::is_ok));
+        assert!(items[200].is_err());
+    }
+
+    #[tokio::test]
+    async fn ends_at_an_empty_page() {
+        let stand_in = StandIn::paged(range, |range| match range {
+            Some((from, _)) => page(from, from, from),
+            None => page(0, 0, PAGE_SIZE),
+        })
+        .await;
+
+        let items = items(&stand_in).await;
+        assert_eq!(items.len(), PAGE_SIZE);
+        assert_eq!(requested_ranges(&stand_in).len(), 1);
+    }
+}
@@ -0,0 +1,234 @@
+use bytes::Bytes;
+
+use crate::MetadataError;
+
+use librespot_core::{Error, Session, SpotifyId};
+
+/// A radio station: tracks picked to go with a seed track, artist, album or playlist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Station {
+    pub uri: String,
+    pub title: String,
+    pub seeds: Vec<String>,
+    pub tracks: Vec<SpotifyId>,
+}
+
+impl Station {
+    /// Generates a station for `seed` with up to `count` tracks, following the station's pages
+    /// until there are enough of them or it runs out.
+    pub async fn get(session: &Session, seed: &SpotifyId, count: usize) -> Result<Self, Error> {
+        let spclient = session.spclient();
+        let response = spclient
+            .get_apollo_station("stations", &seed.to_uri()?, Some(count), Vec::new(), false)
+            .await?;
+        let page = StationPage::try_from(&response)?;
+
+        let mut station = Self {
+            uri: page.uri,
+            title: page.title,
+            seeds: page.seeds,
+            tracks: Vec::with_capacity(count),
+        };
+        station.add_tracks(&page.tracks);
+
+        let mut next_page = page.next_page_url;
+        while station.tracks.len() < count && !next_page.is_empty() {
+            let response = spclient.get_next_page(&next_page).await?;
+            let page = StationPage::try_from(&response)?;
+
+            if station.add_tracks(&page.tracks) == 0 {
+                break;
+            }
+            next_page = page.next_page_url;
+        }
+
+        station.tracks.truncate(count);
+        Ok(station)
+    }
+
+    // Returns how many tracks were new to the station
+    fn add_tracks(&mut self, tracks: &[ItemRef]) -> usize {
+        let before = self.tracks.len();
+        for track in tracks {
+            match SpotifyId::from_uri(&track.uri) {
+                Ok(id) if !self.tracks.contains(&id) => self.tracks.push(id),
+                Ok(_) => (),
+                Err(_) => warn!("Skipping station item with invalid uri {}", track.uri),
+            }
+        }
+        self.tracks.len() - before
+    }
+}
+
+/// Looks up the "inspired by" mix for `track`, a playlist that can be fetched with
+/// [`Playlist`](crate::Playlist).
+pub async fn inspired_by(session: &Session, track: &SpotifyId) -> Result<SpotifyId, Error> {
+    let response = session.spclient().get_radio_for_track(track).await?;
+    let mix: InspiredByMix = serde_json::from_slice(&response)?;
+
+    let item = mix
+        .media_items
+        .first()
+        .ok_or_else(|| Error::unavailable(MetadataError::Empty))?;
+    SpotifyId::from_uri(&item.uri)
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct StationPage {
+    uri: String,
+    title: String,
+    seeds: Vec<String>,
+    tracks: Vec<ItemRef>,
+    next_page_url: String,
+}
+
+impl TryFrom<&Bytes> for StationPage {
+    type Error = Error;
+
+    fn try_from(page: &Bytes) -> Result<Self, Self::Error> {
+        serde_json::from_slice(page).map_err(|err| err.into())
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+struct ItemRef {
+    uri: String,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct InspiredByMix {
+    media_items: Vec<ItemRef>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use librespot_core::stand_in::{track_id, Reply, Request, StandIn};
+    use serde_json::json;
+
+    const SEED: &str = "spotify:track:4uLU6hMCjMI75M1A2tKUQC";
+
+    // A page with the tracks `from` to `to`, pointing to the page after it
+    fn page(from: usize, to: usize, next_page_url: &str) -> Reply {
+        let tracks: Vec<_> = (from..to)
+            .map(|n| json!({ "uri": track_id(n).to_uri().unwrap() }))
+            .collect();
+        let page = json!({
+            "uri": "spotify:station:track:4uLU6hMCjMI75M1A2tKUQC",
+            "title": "Never Gonna Give You Up Radio",
+            "seeds": [SEED],
+            "tracks": tracks,
+            "nextPageUrl": next_page_url,
+        });
+        Reply::ok(page.to_string())
+    }
+
+    // The number of the page after the first that is asked for
+    fn next_page(request: &Request) -> Option<usize> {
+        request
+            .path()
+            .strip_prefix("/radio-apollo/v3/next/")?
+            .parse()
+            .ok()
+    }
+
+    async fn station(stand_in: &StandIn, count: usize) -> Result<Station, Error> {
+        let session = stand_in.session();
+        Station::get(&session, &SpotifyId::from_uri(SEED).unwrap(), count).await
+    }
+
+    #[tokio::test]
+    async fn parses_the_station() {
+        let first = Reply::ok(
+            json!({
+                "uri": "spotify:station:track:4uLU6hMCjMI75M1A2tKUQC",
+                "title": "Never Gonna Give You Up Radio",
+                "seeds": [SEED],
+                "tracks": [
+                    { "uri": track_id(0).to_uri().unwrap(), "metadata": { "decision_id": "x" } },
+                    { "uri": "spotify:track:invalid" },
+                    { "uri": track_id(0).to_uri().unwrap() },
+                    { "uri": track_id(1).to_uri().unwrap() },
+                ],
+                "nextPageUrl": "",
+            })
+            .to_string(),
+        );
+        let stand_in = StandIn::paged(next_page, move |next| match next {
+            None => first.clone(),
+            Some(_) => panic!("there is no next page"),
+        })
+        .await;
+
+        let station = station(&stand_in, 50).await.unwrap();
+        assert_eq!(
+            station,
+            Station {
+                uri: "spotify:station:track:4uLU6hMCjMI75M1A2tKUQC".to_owned(),
+                title: "Never Gonna Give You Up Radio".to_owned(),
+                seeds: vec![SEED.to_owned()],
+                tracks: vec![track_id(0), track_id(1)],
+            }
+        );
+
+        let requests = stand_in.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0]
+            .target
+            .starts_with(&format!("/radio-apollo/v3/stations/{SEED}?")));
+        assert_eq!(requests[0].query("count").as_deref(), Some("50"));
+    }
+
+    #[tokio::test]
+    async fn follows_pages_until_there_are_enough_tracks() {
+        let stand_in = StandIn::paged(next_page, |next| {
+            let n = next.unwrap_or(0);
+            page(
+                n * 20,
+                n * 20 + 20,
+                &format!("hm://radio-apollo/v3/next/{}", n + 1),
+            )
+        })
+        .await;
+
+        let station = station(&stand_in, 50).await.unwrap();
+        assert_eq!(station.tracks, (0..50).map(track_id).collect::<Vec<_>>());
+        assert_eq!(stand_in.served(), 3);
+    }
+
+    #[tokio::test]
+    async fn truncates_to_the_count() {
+        let stand_in = StandIn::paged(next_page, |next| match next {
+            None => page(0, 50, "hm://radio-apollo/v3/next/1"),
+            Some(_) => panic!("the first page has enough tracks"),
+        })
+        .await;
+
+        let station = station(&stand_in, 10).await.unwrap();
+        assert_eq!(station.tracks, (0..10).map(track_id).collect::<Vec<_>>());
+        assert_eq!(stand_in.requests()[0].query("count").as_deref(), Some("10"));
+    }
+
+    #[tokio::test]
+    async fn stops_when_pages_run_out_or_repeat() {
+        let last = StandIn::paged(next_page, |next| match next {
+            None => page(0, 20, ""),
+            Some(_) => panic!("there is no next page"),
+        })
+        .await;
+        assert_eq!(station(&last, 50).await.unwrap().tracks.len(), 20);
+
+        // a page with nothing new would otherwise be asked for again and again
+        let stand_in = StandIn::paged(next_page, |next| match next {
+            None => page(0, 20, "hm://radio-apollo/v3/next/1"),
+            Some(_) => page(10, 20, "hm://radio-apollo/v3/next/1"),
+        })
+        .await;
+        assert_eq!(station(&stand_in, 50).await.unwrap().tracks.len(), 20);
+        assert_eq!(stand_in.served(), 2);
+    }
+}
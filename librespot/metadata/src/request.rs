@@ -1,11 +1,65 @@
-use std::fmt::Write;
+use std::{collections::HashMap, fmt::Write};
+
+use protobuf::Message;
 
 use crate::MetadataError;
 
-use librespot_core::{Error, Session};
+use librespot_core::{Error, Session, SpotifyId};
+use librespot_protocol as protocol;
+
+use protocol::extended_metadata::{
+    BatchedEntityRequest, BatchedExtensionResponse, EntityRequest, ExtensionQuery,
+};
+use protocol::extension_kind::ExtensionKind;
 
 pub type RequestResult = Result<bytes::Bytes, Error>;
 
+/// Requests the `kind` extension of every entity in `ids` at once. The result maps the URIs of
+/// the entities that were found to their data; missing and failed entities are left out.
+pub(crate) async fn request_extended(
+    session: &Session,
+    kind: ExtensionKind,
+    ids: &[SpotifyId],
+) -> Result<HashMap<String, Vec<u8>>, Error> {
+    let mut request = BatchedEntityRequest::new();
+    let header = request.header.mut_or_insert_default();
+    header.country = session.country();
+    header.catalogue = session.get_user_attribute("catalogue").unwrap_or_default();
+
+    for id in ids {
+        let mut entity = EntityRequest::new();
+        entity.entity_uri = id.to_uri()?;
+
+        let mut query = ExtensionQuery::new();
+        query.extension_kind = kind.into();
+        entity.query.push(query);
+
+        request.entity_request.push(entity);
+    }
+
+    let response = session.spclient().get_extended_metadata(request).await?;
+    let response = BatchedExtensionResponse::parse_from_bytes(&response)?;
+
+    let mut found = HashMap::with_capacity(ids.len());
+    for array in response.extended_metadata {
+        if array.extension_kind.enum_value_or_default() != kind {
+            continue;
+        }
+
+        for data in array.extension_data {
+            let status = data.header.status_code;
+            match data.extension_data.into_option() {
+                Some(any) if status == 200 || status == 0 => {
+                    found.insert(data.entity_uri, any.value);
+                }
+                _ => trace!("No {:?} for {} ({})", kind, data.entity_uri, status),
+            }
+        }
+    }
+
+    Ok(found)
+}
+
 #[async_trait]
 pub trait MercuryRequest {
     async fn request(session: &Session, uri: &str) -> RequestResult {
@@ -0,0 +1,592 @@
+use bytes::Bytes;
+
+use librespot_core::{Error, Session, SpotifyId};
+
+/// The kinds of things a search can return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SearchType {
+    Track,
+    Album,
+    Artist,
+    Playlist,
+    Show,
+    Episode,
+}
+
+impl SearchType {
+    pub const ALL: [Self; 6] = [
+        Self::Track,
+        Self::Album,
+        Self::Artist,
+        Self::Playlist,
+        Self::Show,
+        Self::Episode,
+    ];
+
+    /// How the type is named in search requests.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Track => "track",
+            Self::Album => "album",
+            Self::Artist => "artist",
+            Self::Playlist => "playlist",
+            Self::Show => "show",
+            Self::Episode => "episode",
+        }
+    }
+}
+
+/// One page of search results. Kinds that were not asked for are left empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchResults {
+    pub query: String,
+    pub types: Vec<SearchType>,
+    pub limit: usize,
+    pub offset: usize,
+    pub tracks: SearchHits<TrackHit>,
+    pub albums: SearchHits<AlbumHit>,
+    pub artists: SearchHits<ArtistHit>,
+    pub playlists: SearchHits<PlaylistHit>,
+    pub shows: SearchHits<ShowHit>,
+    pub episodes: SearchHits<EpisodeHit>,
+}
+
+/// The results of one kind on this page, and how many of that kind there are in total.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHits<T> {
+    pub total: usize,
+    pub hits: Vec<T>,
+}
+
+impl<T> Default for SearchHits<T> {
+    fn default() -> Self {
+        Self {
+            total: 0,
+            hits: Vec::new(),
+        }
+    }
+}
+
+/// A track found by a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackHit {
+    pub id: SpotifyId,
+    pub name: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub image: Option<String>,
+}
+
+/// An album found by a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlbumHit {
+    pub id: SpotifyId,
+    pub name: String,
+    pub artists: Vec<String>,
+    pub image: Option<String>,
+}
+
+/// An artist found by a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtistHit {
+    pub id: SpotifyId,
+    pub name: String,
+    pub image: Option<String>,
+}
+
+/// A playlist found by a search, with the name of whoever made it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaylistHit {
+    pub id: SpotifyId,
+    pub name: String,
+    pub author: Option<String>,
+    pub image: Option<String>,
+}
+
+/// A show found by a search, with the name of its publisher.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShowHit {
+    pub id: SpotifyId,
+    pub name: String,
+    pub author: Option<String>,
+    pub image: Option<String>,
+}
+
+/// An episode found by a search, with the name of the show it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpisodeHit {
+    pub id: SpotifyId,
+    pub name: String,
+    pub show: Option<String>,
+    pub image: Option<String>,
+}
+
+impl SearchResults {
+    /// Searches for `query`, returning up to `limit` results of each of `types` starting at
+    /// `offset`. An empty `types` returns every kind.
+    pub async fn get(
+        session: &Session,
+        query: &str,
+        types: &[SearchType],
+        limit: usize,
+        offset: usize,
+    ) -> Result<Self, Error> {
+        let names: Vec<_> = types.iter().map(SearchType::as_str).collect();
+        let response = session
+            .spclient()
+            .search(query, &names, limit, offset)
+            .await?;
+        Self::parse(&response, query, types, limit, offset)
+    }
+
+    /// Fetches the page after this one, if there is any.
+    pub async fn next_page(&self, session: &Session) -> Result<Option<Self>, Error> {
+        if !self.has_more() {
+            return Ok(None);
+        }
+
+        let offset = self.offset + self.limit;
+        Self::get(session, &self.query, &self.types, self.limit, offset)
+            .await
+            .map(Some)
+    }
+
+    /// Whether any of the kinds asked for has results after this page.
+    pub fn has_more(&self) -> bool {
+        let end = self.offset + self.limit;
+        self.totals().any(|total| total > end)
+    }
+
+    /// The number of hits on this page, over all kinds.
+    pub fn len(&self) -> usize {
+        self.tracks.hits.len()
+            + self.albums.hits.len()
+            + self.artists.hits.len()
+            + self.playlists.hits.len()
+            + self.shows.hits.len()
+            + self.episodes.hits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn totals(&self) -> impl Iterator<Item = usize> {
+        [
+            self.tracks.total,
+            self.albums.total,
+            self.artists.total,
+            self.playlists.total,
+            self.shows.total,
+            self.episodes.total,
+        ]
+        .into_iter()
+    }
+
+    fn parse(
+        response: &Bytes,
+        query: &str,
+        types: &[SearchType],
+        limit: usize,
+        offset: usize,
+    ) -> Result<Self, Error> {
+        let response: SearchResponse = serde_json::from_slice(response)?;
+        let results = response.results;
+
+        let types = if types.is_empty() {
+            SearchType::ALL.to_vec()
+        } else {
+            types.to_vec()
+        };
+
+        // the service may still send types that were not asked for
+        let asked = |kind| types.contains(&kind);
+
+        Ok(Self {
+            query: query.to_owned(),
+            limit,
+            offset,
+            tracks: results.tracks.pick(asked(SearchType::Track)),
+            albums: results.albums.pick(asked(SearchType::Album)),
+            artists: results.artists.pick(asked(SearchType::Artist)),
+            playlists: results.playlists.pick(asked(SearchType::Playlist)),
+            shows: results.shows.pick(asked(SearchType::Show)),
+            episodes: results.episodes.pick(asked(SearchType::Episode)),
+            types,
+        })
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SearchResponse {
+    results: RawResults,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct RawResults {
+    tracks: RawHits<RawTrack>,
+    albums: RawHits<RawAlbum>,
+    artists: RawHits<RawArtist>,
+    playlists: RawHits<RawPlaylist>,
+    #[serde(alias = "podcasts")]
+    shows: RawHits<RawShow>,
+    episodes: RawHits<RawEpisode>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(default)]
+struct RawHits<R> {
+    total: usize,
+    hits: Vec<R>,
+}
+
+impl<R> Default for RawHits<R> {
+    fn default() -> Self {
+        Self {
+            total: 0,
+            hits: Vec::new(),
+        }
+    }
+}
+
+impl<R: RawHit> RawHits<R> {
+    // Keeps the hits with a valid uri, or none at all if the kind was not asked for
+    fn pick(self, asked: bool) -> SearchHits<R::Hit> {
+        if !asked {
+            return SearchHits::default();
+        }
+
+        let hits = self
+            .hits
+            .into_iter()
+            .filter_map(|hit| match SpotifyId::from_uri(hit.uri()) {
+                Ok(id) => Some(hit.into_hit(id)),
+                Err(_) => {
+                    warn!("Skipping search hit with invalid uri {}", hit.uri());
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        SearchHits {
+            total: self.total.max(hits.len()),
+            hits,
+        }
+    }
+}
+
+trait RawHit {
+    type Hit;
+
+    fn uri(&self) -> &str;
+    fn into_hit(self, id: SpotifyId) -> Self::Hit;
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct RawName {
+    name: String,
+}
+
+fn names(named: Vec<RawName>) -> Vec<String> {
+    named.into_iter().map(|named| named.name).collect()
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct RawTrack {
+    uri: String,
+    name: String,
+    artists: Vec<RawName>,
+    album: Option<RawName>,
+    image: Option<String>,
+}
+
+impl RawHit for RawTrack {
+    type Hit = TrackHit;
+
+    fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    fn into_hit(self, id: SpotifyId) -> TrackHit {
+        TrackHit {
+            id,
+            name: self.name,
+            artists: names(self.artists),
+            album: self.album.map(|album| album.name),
+            image: self.image,
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct RawAlbum {
+    uri: String,
+    name: String,
+    artists: Vec<RawName>,
+    image: Option<String>,
+}
+
+impl RawHit for RawAlbum {
+    type Hit = AlbumHit;
+
+    fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    fn into_hit(self, id: SpotifyId) -> AlbumHit {
+        AlbumHit {
+            id,
+            name: self.name,
+            artists: names(self.artists),
+            image: self.image,
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct RawArtist {
+    uri: String,
+    name: String,
+    image: Option<String>,
+}
+
+impl RawHit for RawArtist {
+    type Hit = ArtistHit;
+
+    fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    fn into_hit(self, id: SpotifyId) -> ArtistHit {
+        ArtistHit {
+            id,
+            name: self.name,
+            image: self.image,
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct RawPlaylist {
+    uri: String,
+    name: String,
+    author: Option<String>,
+    image: Option<String>,
+}
+
+impl RawHit for RawPlaylist {
+    type Hit = PlaylistHit;
+
+    fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    fn into_hit(self, id: SpotifyId) -> PlaylistHit {
+        PlaylistHit {
+            id,
+            name: self.name,
+            author: self.author,
+            image: self.image,
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct RawShow {
+    uri: String,
+    name: String,
+    #[serde(alias = "publisher")]
+    author: Option<String>,
+    image: Option<String>,
+}
+
+impl RawHit for RawShow {
+    type Hit = ShowHit;
+
+    fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    fn into_hit(self, id: SpotifyId) -> ShowHit {
+        ShowHit {
+            id,
+            name: self.name,
+            author: self.author,
+            image: self.image,
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct RawEpisode {
+    uri: String,
+    name: String,
+    show: Option<RawName>,
+    image: Option<String>,
+}
+
+impl RawHit for RawEpisode {
+    type Hit = EpisodeHit;
+
+    fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    fn into_hit(self, id: SpotifyId) -> EpisodeHit {
+        EpisodeHit {
+            id,
+            name: self.name,
+            show: self.show.map(|show| show.name),
+            image: self.image,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use librespot_core::stand_in::{Reply, StandIn};
+
+    const FIRST_PAGE: &str = r#"{"results": {
+        "tracks": {"total": 3, "hits": [
+            {"uri": "spotify:track:4uLU6hMCjMI75M1A2tKUQC", "name": "Never Gonna Give You Up",
+             "artists": [{"name": "Rick Astley", "uri": "spotify:artist:0gxyHStUsqpMadRV0Di1Qt"}],
+             "album": {"name": "Whenever You Need Somebody",
+                       "uri": "spotify:album:6XhjNHCyCDyyGJRM5mg40G"},
+             "image": "https://i.scdn.co/image/ab67616d00001e02"},
+            {"uri": "not a uri", "name": "Broken"},
+            {"uri": "spotify:track:0yc6Gst2xkRu0eMLeRMGCX", "name": "Together Forever",
+             "artists": [{"name": "Rick Astley"}]}
+        ]},
+        "artists": {"total": 1, "hits": [
+            {"uri": "spotify:artist:0gxyHStUsqpMadRV0Di1Qt", "name": "Rick Astley"}
+        ]},
+        "playlists": {"total": 1, "hits": [
+            {"uri": "spotify:playlist:37i9dQZF1DXcBWIGoYBM5M", "name": "Today's Top Hits",
+             "author": "Spotify"}
+        ]},
+        "podcasts": {"total": 1, "hits": [
+            {"uri": "spotify:show:4rOoJ6Egrf8K2IrywzwOMk", "name": "The Joe Budden Podcast",
+             "publisher": "The Joe Budden Network"}
+        ]}
+    }}"#;
+
+    const SECOND_PAGE: &str = r#"{"results": {
+        "tracks": {"total": 3, "hits": [
+            {"uri": "spotify:track:6rqhFgbbKwnb9MLmUQDhG6", "name": "Whenever You Need Somebody"}
+        ]},
+        "episodes": {}
+    }}"#;
+
+    // Serves the search endpoint with the canned page its `offset` asks for
+    async fn stand_in() -> StandIn {
+        StandIn::new(|request| {
+            if !request
+                .target
+                .starts_with("/searchview/km/v4/search/rick+astley?")
+            {
+                return Reply::new(http::StatusCode::NOT_FOUND);
+            }
+
+            match request.query("offset").as_deref() {
+                Some("0") => Reply::ok(FIRST_PAGE),
+                _ => Reply::ok(SECOND_PAGE),
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn parses_every_kind() {
+        let stand_in = stand_in().await;
+        let session = stand_in.session();
+        let results = SearchResults::get(&session, "rick astley", &[], 2, 0)
+            .await
+            .unwrap();
+
+        let request = &stand_in.requests()[0];
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.query("limit").as_deref(), Some("2"));
+        assert_eq!(request.query("types"), None);
+
+        let tracks: Vec<_> = results
+            .tracks
+            .hits
+            .iter()
+            .map(|hit| hit.name.as_str())
+            .collect();
+        assert_eq!(tracks, ["Never Gonna Give You Up", "Together Forever"]);
+        assert_eq!(results.tracks.total, 3);
+        assert_eq!(results.tracks.hits[0].artists, ["Rick Astley"]);
+        assert_eq!(
+            results.tracks.hits[0].album.as_deref(),
+            Some("Whenever You Need Somebody")
+        );
+        assert_eq!(
+            results.tracks.hits[0].id,
+            SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap()
+        );
+
+        assert_eq!(results.artists.hits.len(), 1);
+        assert_eq!(results.playlists.hits[0].author.as_deref(), Some("Spotify"));
+        assert_eq!(results.shows.hits[0].name, "The Joe Budden Podcast");
+        assert_eq!(
+            results.shows.hits[0].author.as_deref(),
+            Some("The Joe Budden Network")
+        );
+        assert!(results.albums.hits.is_empty());
+        assert!(results.episodes.hits.is_empty());
+        assert_eq!(results.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn asks_for_the_requested_types() {
+        let stand_in = stand_in().await;
+        let session = stand_in.session();
+        let types = [SearchType::Artist, SearchType::Show];
+        let results = SearchResults::get(&session, "rick astley", &types, 2, 0)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stand_in.requests()[0].query("types").as_deref(),
+            Some("artist,show")
+        );
+        assert!(results.tracks.hits.is_empty());
+        assert!(results.playlists.hits.is_empty());
+        assert_eq!(results.artists.hits.len(), 1);
+        assert_eq!(results.shows.hits.len(), 1);
+        assert!(!results.has_more());
+    }
+
+    #[tokio::test]
+    async fn pages_until_the_total() {
+        let stand_in = stand_in().await;
+        let session = stand_in.session();
+
+        let first = SearchResults::get(&session, "rick astley", &[SearchType::Track], 2, 0)
+            .await
+            .unwrap();
+        assert!(first.has_more());
+
+        let second = first.next_page(&session).await.unwrap().unwrap();
+        assert_eq!(second.offset, 2);
+        assert_eq!(second.tracks.hits[0].name, "Whenever You Need Somebody");
+        assert!(!second.has_more());
+        assert!(second.next_page(&session).await.unwrap().is_none());
+
+        let requests = stand_in.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].query("offset").as_deref(), Some("2"));
+        assert_eq!(requests[1].query("types").as_deref(), Some("track"));
+    }
+}
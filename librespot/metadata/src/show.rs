@@ -8,6 +8,7 @@ use crate::{
 use librespot_core::{Error, Session, SpotifyId};
 
 use librespot_protocol as protocol;
+use protocol::extension_kind::ExtensionKind;
 pub use protocol::metadata::show::ConsumptionOrder as ShowConsumptionOrder;
 pub use protocol::metadata::show::MediaType as ShowMediaType;
 
@@ -35,6 +36,8 @@ pub struct Show {
 #[async_trait]
 impl Metadata for Show {
     type Message = protocol::metadata::Show;
+    const EXTENSION_KIND: Option<ExtensionKind> = Some(ExtensionKind::SHOW_V4);
+    const CACHE: bool = true;
 
     async fn request(session: &Session, show_id: &SpotifyId) -> RequestResult {
         session.spclient().get_show_metadata(show_id).await
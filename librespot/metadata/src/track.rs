@@ -8,7 +8,8 @@ use uuid::Uuid;
 use crate::{
     artist::{Artists, ArtistsWithRole},
     audio::file::AudioFiles,
-    availability::Availabilities,
+    audio::item::available_for_user,
+    availability::{AudioItemAvailability, Availabilities, UnavailabilityReason},
     content_rating::ContentRatings,
     external_id::ExternalIds,
     restriction::Restrictions,
@@ -17,8 +18,9 @@ use crate::{
     Album, Metadata, RequestResult,
 };
 
-use librespot_core::{date::Date, Error, Session, SpotifyId};
+use librespot_core::{date::Date, session::UserData, Error, Session, SpotifyId};
 use librespot_protocol as protocol;
+use protocol::extension_kind::ExtensionKind;
 
 #[derive(Debug, Clone)]
 pub struct Track {
@@ -54,9 +56,24 @@ pub struct Tracks(pub Vec<SpotifyId>);
 
 impl_deref_wrapped!(Tracks, Vec<SpotifyId>);
 
+impl Track {
+    /// Checks whether the track can be played by `user_data`'s account, based on its release
+    /// date, availability windows and the user's country and catalogue. This does not look at
+    /// any alternatives.
+    pub fn available_for_user(&self, user_data: &UserData) -> AudioItemAvailability {
+        if Date::now_utc() < self.earliest_live_timestamp {
+            return Err(UnavailabilityReason::Embargo);
+        }
+
+        available_for_user(user_data, &self.availability, &self.restrictions)
+    }
+}
+
 #[async_trait]
 impl Metadata for Track {
     type Message = protocol::metadata::Track;
+    const EXTENSION_KIND: Option<ExtensionKind> = Some(ExtensionKind::TRACK_V4);
+    const CACHE: bool = true;
 
     async fn request(session: &Session, track_id: &SpotifyId) -> RequestResult {
         session.spclient().get_track_metadata(track_id).await
@@ -134,16 +134,31 @@ fn get_code(redirect_url: &str) -> Result<AuthorizationCode, OAuthError> {
     Ok(code)
 }
 
-/// Prompt for redirect URI on stdin and return auth code.
+/// Return auth code from either a full redirect URI or the bare code pasted by the user.
+fn get_code_from_input(input: &str) -> Result<AuthorizationCode, OAuthError> {
+    if Url::parse(input).is_ok() {
+        return get_code(input);
+    }
+
+    if input.is_empty() || input.contains(char::is_whitespace) {
+        return Err(OAuthError::AuthCodeNotFound {
+            uri: input.to_string(),
+        });
+    }
+
+    Ok(AuthorizationCode::new(input.to_string()))
+}
+
+/// Prompt for redirect URI or code on stdin and return auth code.
 fn get_authcode_stdin() -> Result<AuthorizationCode, OAuthError> {
-    println!("Provide redirect URL");
+    println!("Provide redirect URL or code");
     let mut buffer = String::new();
     let stdin = io::stdin();
     stdin
         .read_line(&mut buffer)
         .map_err(|_| OAuthError::AuthCodeStdinRead)?;
 
-    get_code(buffer.trim())
+    get_code_from_input(buffer.trim())
 }
 
 /// Spawn HTTP server at provided socket address to accept OAuth callback and return auth code.
@@ -213,6 +228,7 @@ pub struct OAuthClient {
     scopes: Vec<String>,
     redirect_uri: String,
     should_open_url: bool,
+    manual_code_entry: bool,
     message: String,
     client: BasicClient,
 }
@@ -242,6 +258,16 @@ impl OAuthClient {
         pkce_verifier
     }
 
+    /// Collects the auth code, either from a local callback server or from standard input.
+    fn get_authcode(&self) -> Result<AuthorizationCode, OAuthError> {
+        match get_socket_address(&self.redirect_uri) {
+            Some(addr) if !self.manual_code_entry => {
+                get_authcode_listener(addr, self.message.clone())
+            }
+            _ => get_authcode_stdin(),
+        }
+    }
+
     fn build_token(
         &self,
         resp: StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>,
@@ -272,10 +298,7 @@ impl OAuthClient {
     pub fn get_access_token(&self) -> Result<OAuthToken, OAuthError> {
         let pkce_verifier = self.set_auth_url();
 
-        let code = match get_socket_address(&self.redirect_uri) {
-            Some(addr) => get_authcode_listener(addr, self.message.clone()),
-            _ => get_authcode_stdin(),
-        }?;
+        let code = self.get_authcode()?;
         trace!("Exchange {code:?} for access token");
 
         let (tx, rx) = mpsc::channel();
@@ -312,10 +335,7 @@ impl OAuthClient {
     pub async fn get_access_token_async(&self) -> Result<OAuthToken, OAuthError> {
         let pkce_verifier = self.set_auth_url();
 
-        let code = match get_socket_address(&self.redirect_uri) {
-            Some(addr) => get_authcode_listener(addr, self.message.clone()),
-            _ => get_authcode_stdin(),
-        }?;
+        let code = self.get_authcode()?;
         trace!("Exchange {code:?} for access token");
 
         let resp = self
@@ -349,6 +369,7 @@ pub struct OAuthClientBuilder {
     redirect_uri: String,
     scopes: Vec<String>,
     should_open_url: bool,
+    manual_code_entry: bool,
     message: String,
 }
 
@@ -362,6 +383,7 @@ impl OAuthClientBuilder {
             redirect_uri: redirect_uri.to_string(),
             scopes: scopes.into_iter().map(Into::into).collect(),
             should_open_url: false,
+            manual_code_entry: false,
             message: String::from("Go back to your terminal :)"),
         }
     }
@@ -373,6 +395,14 @@ impl OAuthClientBuilder {
         self
     }
 
+    /// When this function is added to the building process pipeline, the redirect URL (or just
+    /// the code in it) is always read from standard input, even if `redirect_uri` points to a
+    /// local port. This allows logging in on headless systems, using a browser on another machine.
+    pub fn with_manual_code_entry(mut self) -> Self {
+        self.manual_code_entry = true;
+        self
+    }
+
     /// When this function is added to the building process pipeline, the body of the response to
     /// the callback request will be `message`. This is useful to load custom HTMLs to that &str.
     pub fn with_custom_message(mut self, message: &str) -> Self {
@@ -404,6 +434,7 @@ impl OAuthClientBuilder {
         Ok(OAuthClient {
             scopes: self.scopes,
             should_open_url: self.should_open_url,
+            manual_code_entry: self.manual_code_entry,
             message: self.message,
             redirect_uri: self.redirect_uri,
             client,
@@ -521,6 +552,25 @@ mod test {
         assert_eq!(get_socket_address("https://127.0.0.1/foo"), None);
     }
 
+    #[test]
+    fn get_code_from_input_url() {
+        let code = get_code_from_input("http://127.0.0.1:8888/login?code=abc123&state=xyz");
+        assert_eq!(code.unwrap().secret(), "abc123");
+
+        assert!(matches!(
+            get_code_from_input("http://127.0.0.1:8888/login?state=xyz"),
+            Err(OAuthError::AuthCodeNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn get_code_from_input_bare_code() {
+        assert_eq!(get_code_from_input("abc123").unwrap().secret(), "abc123");
+
+        assert!(get_code_from_input("").is_err());
+        assert!(get_code_from_input("abc 123").is_err());
+    }
+
     #[test]
     fn get_socket_address_localhost() {
         let localhost_v4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 1234);
@@ -45,7 +45,7 @@ pub const PCM_AT_0DBFS: f64 = 1.0;
 
 // Spotify inserts a custom Ogg packet at the start with custom metadata values, that you would
 // otherwise expect in Vorbis comments. This packet isn't well-formed and players may balk at it.
-const SPOTIFY_OGG_HEADER_END: u64 = 0xa7;
+pub const SPOTIFY_OGG_HEADER_END: u64 = 0xa7;
 
 pub type PlayerResult = Result<(), Error>;
 
@@ -0,0 +1,105 @@
+use crate::Error;
+use crate::auth;
+use crate::highlight;
+
+use crate::config::Network;
+use crate::profile::Profile;
+use crate::queue::Queue;
+use crate::spotify::Session;
+use crate::store::CredentialStore;
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tokio::sync::{Mutex, MutexGuard};
+
+pub const MAX_RECONNECTS: u32 = 5;
+
+/// The state commands work with, shared between the prompt and the download queue.
+#[derive(Clone)]
+pub struct App(Arc<State>);
+
+struct State {
+    session: RwLock<Session>,
+    store: Mutex<CredentialStore>,
+    profile: RwLock<Profile>,
+
+    /// Network settings given on the command line, these win over the profile's.
+    network: Network,
+
+    queue: Queue,
+}
+
+impl App {
+    pub fn new(session: Session, store: CredentialStore, profile: Profile, network: Network) -> Self {
+        Self(Arc::new(State {
+            session: RwLock::new(session),
+            store: Mutex::new(store),
+            profile: RwLock::new(profile),
+            network,
+            queue: Queue::new(),
+        }))
+    }
+
+    /// The current session, this changes when reconnecting or switching profiles.
+    pub fn session(&self) -> Session {
+        self.0.session.read().unwrap().clone()
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.0.profile.read().unwrap().clone()
+    }
+
+    pub async fn store(&self) -> MutexGuard<'_, CredentialStore> {
+        self.0.store.lock().await
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.0.network
+    }
+
+    pub fn queue(&self) -> &Queue {
+        &self.0.queue
+    }
+
+    /// Swaps in the session, credentials and settings of another profile.
+    pub async fn switch(&self, session: Session, store: CredentialStore, profile: Profile) {
+        *self.0.store.lock().await = store;
+        *self.0.profile.write().unwrap() = profile;
+
+        let previous = std::mem::replace(&mut *self.0.session.write().unwrap(), session);
+        previous.shutdown();
+    }
+
+    /// Replaces a session that lost its connection with a new one, waiting longer after every
+    /// failed attempt.
+    pub async fn reconnect(&self) -> Result<(), Error> {
+        let mut store = self.0.store.lock().await;
+
+        // someone else may have reconnected while this waited for the store
+        if !self.session().is_invalid() {
+            return Ok(());
+        }
+
+        let queue = self.queue();
+        for attempt in 1..=MAX_RECONNECTS {
+            queue.print(format!("connection lost, reconnecting (attempt {}/{})", attempt, MAX_RECONNECTS));
+
+            let session = Session::new(self.session().config().clone(), None);
+            match auth::reconnect(&session, &mut store).await {
+                Ok(()) => {
+                    queue.print(format!("reconnected as {}", highlight.apply_to(session.username())));
+                    *self.0.session.write().unwrap() = session;
+                    return Ok(());
+                },
+                Err(err) => queue.print(format!("reconnecting failed: {}", err)),
+            }
+
+            if attempt < MAX_RECONNECTS {
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+            }
+        }
+
+        Err(Error::Login(format!("could not reconnect after {} attempts", MAX_RECONNECTS)))
+    }
+}
@@ -0,0 +1,180 @@
+use crate::Error;
+use crate::highlight;
+
+use crate::spotify::Credentials;
+use crate::spotify::Session;
+use crate::store::{self, CredentialStore, Secrets};
+
+use librespot::oauth::{OAuthClient, OAuthClientBuilder, OAuthToken};
+use librespot::protocol::authentication::AuthenticationType;
+
+use std::fs;
+use std::path::Path;
+
+const CLIENT_ID: &str = "c85b2435db4948bab5fcd3386b77170c";
+const CALLBACK_URL: &str = "http://localhost:8888/callback";
+const SCOPES: [&str; 2] = ["playlist-read-private", "streaming"];
+
+// older versions kept their secrets in plain text inside the download directory
+const LEGACY_CREDENTIALS_FILE: &str = "credentials.json";
+const LEGACY_REFRESH_TOKEN_FILE: &str = "refresh_token.txt";
+const LEGACY_TOKEN_FILE: &str = "access_token.txt";
+
+/// How the user logs in when there are no usable stored credentials.
+#[derive(Default)]
+pub struct LoginOptions {
+    /// Print the auth URL instead of opening a browser and read the redirect back from stdin,
+    /// for machines without a browser.
+    pub headless: bool,
+
+    /// Encrypt the stored credentials with a passphrase, if they aren't already.
+    pub encrypt: bool,
+}
+
+fn oauth_client(headless: bool) -> Result<OAuthClient, Error> {
+    let builder = OAuthClientBuilder::new(CLIENT_ID, CALLBACK_URL, SCOPES.to_vec())
+    	.with_custom_message("go back to your terminal :)");
+
+    let builder = if headless {
+        builder.with_manual_code_entry()
+    } else {
+        builder.open_in_browser()
+    };
+
+    Ok(builder.build()?)
+}
+
+fn keep_refresh_token(secrets: &mut Secrets, token: &OAuthToken) {
+    if !token.refresh_token.is_empty() {
+        secrets.refresh_token = Some(token.refresh_token.clone());
+    }
+}
+
+/// Moves the plain text files written by older versions into the credential store.
+fn migrate_legacy() -> Secrets {
+    let credentials = fs::read_to_string(LEGACY_CREDENTIALS_FILE).ok()
+        .and_then(|data| serde_json::from_str(&data).ok());
+    let refresh_token = fs::read_to_string(LEGACY_REFRESH_TOKEN_FILE).ok()
+        .map(|token| token.trim().to_owned());
+
+    for file in [LEGACY_CREDENTIALS_FILE, LEGACY_REFRESH_TOKEN_FILE, LEGACY_TOKEN_FILE] {
+        let _ = store::wipe_file(Path::new(file));
+    }
+
+    Secrets { credentials, refresh_token }
+}
+
+/// Stores the reusable credentials `session` was given when it connected.
+fn remember(session: &Session, store: &CredentialStore, mut secrets: Secrets) {
+    secrets.credentials = Some(Credentials {
+        username: Some(session.username()),
+        auth_type: AuthenticationType::AUTHENTICATION_STORED_SPOTIFY_CREDENTIALS,
+        auth_data: session.auth_data(),
+    });
+
+    if let Err(err) = store.save(&secrets) {
+        println!("could not save credentials, you will have to log in again next time: {}", err);
+    }
+}
+
+/// Tries the stored reusable credentials, then the stored refresh token, without asking the user
+/// for anything.
+async fn connect_stored(session: &Session, secrets: &mut Secrets) -> bool {
+    if let Some(creds) = secrets.credentials.clone() {
+        if session.connect(creds, false).await.is_ok() {
+            return true;
+        }
+    }
+
+    let (Some(refresh_token), Ok(client)) = (secrets.refresh_token.clone(), oauth_client(false)) else {
+        return false;
+    };
+
+    match client.refresh_token_async(&refresh_token).await {
+        Ok(token) => {
+            keep_refresh_token(secrets, &token);
+            let creds = Credentials::with_access_token(token.access_token);
+            session.connect(creds, false).await.is_ok()
+        },
+        Err(_) => false,
+    }
+}
+
+/// Logs `session` in, trying the stored reusable credentials first, then the stored refresh
+/// token, and only then asking the user to log in through their browser.
+pub async fn connect(session: &Session, store: &mut CredentialStore, options: &LoginOptions) -> Result<(), Error> {
+    let mut secrets = if store.exists() { store.load()? } else { migrate_legacy() };
+
+    if options.encrypt && !store.is_encrypted() {
+        store.set_passphrase(store::prompt_new_passphrase()?);
+    }
+
+    if !connect_stored(session, &mut secrets).await {
+        if options.headless {
+            println!("open the link below in a browser on any machine and log in.");
+            println!("the page you end up on will not load, that is expected: copy its address (or the code in it) and paste it here.");
+        }
+
+        let token = oauth_client(options.headless)?.get_access_token()?;
+        keep_refresh_token(&mut secrets, &token);
+        session.connect(Credentials::with_access_token(token.access_token), false).await?;
+    }
+
+    remember(session, store, secrets);
+    Ok(())
+}
+
+/// Logs a fresh `session` in with the stored credentials only, for replacing a session that lost
+/// its connection while nobody might be watching.
+pub async fn reconnect(session: &Session, store: &mut CredentialStore) -> Result<(), Error> {
+    let mut secrets = store.load()?;
+    if !connect_stored(session, &mut secrets).await {
+        return Err(Error::Login("the stored credentials were not accepted".to_owned()));
+    }
+
+    remember(session, store, secrets);
+    Ok(())
+}
+
+/// Logs `session` in with the credentials in `path`, either a credentials.json written by
+/// librespot or spotify-dl's own store copied from another machine, and stores them for the next
+/// start.
+pub async fn import(session: &Session, store: &CredentialStore, path: &Path) -> Result<(), Error> {
+    let data = fs::read_to_string(path)?;
+    let creds = match serde_json::from_str::<Credentials>(&data) {
+        Ok(creds) => creds,
+        Err(_) => CredentialStore::at(path).load()?.credentials
+            .ok_or_else(|| Error::Login(format!("{} does not contain any credentials", path.display())))?,
+    };
+
+    session.connect(creds, false).await?;
+    remember(session, store, Secrets::default());
+    Ok(())
+}
+
+/// Wipes all stored credentials, the next start will require logging in again.
+pub fn logout(store: &mut CredentialStore) -> Result<(), Error> {
+    store.wipe()?;
+    let _ = migrate_legacy();
+    Ok(())
+}
+
+/// Prints who is logged in, without showing any of the secrets.
+pub async fn whoami(session: &Session, store: &CredentialStore) {
+    println!("account:     {}", highlight.apply_to(session.username()));
+    println!("country:     {}", session.country());
+    if let Some(product) = session.get_user_attribute("type") {
+        println!("product:     {}", product);
+    }
+
+    match session.login5().auth_token().await {
+        Ok(token) => {
+            let left = token.expires_in.saturating_sub(token.timestamp.elapsed());
+            println!("token:       expires in {} minutes", left.as_secs() / 60);
+        },
+        Err(err) => println!("token:       unavailable ({})", err),
+    }
+
+    let encryption = if store.is_encrypted() { "encrypted" } else { "not encrypted" };
+    println!("credentials: {} ({})", store.path().display(), encryption);
+}
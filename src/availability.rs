@@ -0,0 +1,70 @@
+use crate::download;
+use crate::spotify;
+
+use crate::spotify::Session;
+
+use librespot::metadata::Track;
+use librespot::metadata::availability::UnavailabilityReason;
+
+/// Whether, and how, a track can be downloaded by the logged in account.
+pub enum Availability {
+    Available,
+
+    /// The track itself is restricted, but this alternative release of it can be downloaded.
+    Alternative(Box<Track>),
+
+    Restricted(UnavailabilityReason),
+
+    /// The track has no ogg/vorbis file that could be written to disk.
+    NoFile,
+}
+
+impl Availability {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::Available      => "available",
+            Self::Alternative(_) => "alternative",
+            Self::NoFile         => "no ogg file",
+            Self::Restricted(reason) => match reason {
+                UnavailabilityReason::Blacklisted    => "blocked",
+                UnavailabilityReason::Embargo        => "not released",
+                UnavailabilityReason::NoData         => "no data",
+                UnavailabilityReason::NotWhitelisted => "region locked",
+            },
+        }
+    }
+
+    pub fn is_downloadable(&self) -> bool {
+        matches!(self, Self::Available | Self::Alternative(_))
+    }
+}
+
+fn check_one(track: &Track, session: &Session) -> Availability {
+    if let Err(reason) = track.available_for_user(&session.user_data()) {
+        return Availability::Restricted(reason);
+    }
+
+    if download::select_file(track).is_none() {
+        return Availability::NoFile;
+    }
+
+    Availability::Available
+}
+
+/// Checks `track` against the account's country and catalogue before anything is fetched,
+/// falling back to the first of its alternatives that is available.
+pub async fn check(track: &Track, session: &Session) -> Availability {
+    let availability = check_one(track, session);
+    if availability.is_downloadable() || track.alternatives.is_empty() {
+        return availability;
+    }
+
+    let alternatives = spotify::get_tracks_metadata(&track.alternatives, session).await;
+    for alternative in alternatives.into_iter().flatten() {
+        if check_one(&alternative, session).is_downloadable() {
+            return Availability::Alternative(Box::new(alternative));
+        }
+    }
+
+    availability
+}
@@ -0,0 +1,226 @@
+use crate::Error;
+use crate::spotify::SessionConfig;
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+
+const PROXY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How to reach Spotify, set per profile and overridable on the command line.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Network {
+    /// An HTTP(S) or SOCKS proxy that all connections are tunneled through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+
+    /// Only connect to access points on this port, e.g. 443 behind firewalls.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ap_port: Option<u16>,
+
+    /// Where partially downloaded files are kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tmp_dir: Option<PathBuf>,
+
+    /// The most that all downloads together may fetch per second, e.g. "800K" or "2M".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rate: Option<String>,
+}
+
+impl Network {
+    /// These settings, with anything set in `overrides` taking precedence.
+    pub fn with(&self, overrides: &Network) -> Network {
+        Network {
+            proxy: overrides.proxy.clone().or_else(|| self.proxy.clone()),
+            ap_port: overrides.ap_port.or(self.ap_port),
+            tmp_dir: overrides.tmp_dir.clone().or_else(|| self.tmp_dir.clone()),
+            max_rate: overrides.max_rate.clone().or_else(|| self.max_rate.clone()),
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::Config(message)
+}
+
+async fn check_proxy(proxy: &str) -> Result<Url, Error> {
+    let url = Url::parse(proxy).map_err(|err| invalid(format!("'{}' is not a valid proxy url: {}", proxy, err)))?;
+
+    let default_port = match url.scheme() {
+        "http" => 80,
+        "https" => 443,
+        "socks4" | "socks4a" | "socks5" | "socks5h" => 1080,
+        scheme => return Err(invalid(format!("unknown proxy scheme '{}', use http://, https://, socks4://, socks4a://, socks5:// or socks5h://", scheme))),
+    };
+
+    let host = url.host_str().ok_or_else(|| invalid(format!("the proxy url '{}' has no host", proxy)))?;
+    let port = url.port().unwrap_or(default_port);
+
+    match tokio::time::timeout(PROXY_TIMEOUT, TcpStream::connect((host, port))).await {
+        Ok(Ok(_)) => Ok(url),
+        Ok(Err(err)) => Err(invalid(format!("the proxy {}:{} is unreachable: {}", host, port, err))),
+        Err(_) => Err(invalid(format!("the proxy {}:{} did not answer within {} seconds", host, port, PROXY_TIMEOUT.as_secs()))),
+    }
+}
+
+fn check_tmp_dir(dir: &PathBuf) -> Result<(), Error> {
+    let probe = dir.join(".spotify-dl-probe");
+    fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&probe, b""))
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|err| invalid(format!("can not use {} for temporary files: {}", dir.display(), err)))
+}
+
+/// Parses a rate in bytes per second, with an optional K, M or G suffix (powers of 1024).
+fn parse_rate(rate: &str) -> Result<NonZeroU32, Error> {
+    let trimmed = rate.trim().trim_end_matches(['/', 's', 'B']);
+    let (number, unit) = match trimmed.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&trimmed[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&trimmed[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&trimmed[..i], 1 << 30),
+        _ => (trimmed, 1),
+    };
+
+    let bytes = number.trim().parse::<f64>().ok()
+        .map(|number| number * unit as f64)
+        .filter(|bytes| *bytes >= 1.0 && *bytes <= u32::MAX as f64)
+        .ok_or_else(|| invalid(format!("'{}' is not a valid rate, use bytes per second like 800K or 2M", rate)))?;
+
+    Ok(NonZeroU32::new(bytes as u32).expect("rate should be at least one byte"))
+}
+
+/// The most bytes per second `network` allows downloads to fetch, if it limits them.
+pub fn max_rate(network: &Network) -> Result<Option<NonZeroU32>, Error> {
+    network.max_rate.as_deref().map(parse_rate).transpose()
+}
+
+/// Checks `network` and turns it into the config sessions are created with.
+pub async fn session_config(network: &Network) -> Result<SessionConfig, Error> {
+    let mut config = SessionConfig::default();
+
+    if let Some(ref proxy) = network.proxy {
+        config.proxy = Some(check_proxy(proxy).await?);
+    }
+
+    if let Some(port) = network.ap_port {
+        if port == 0 {
+            return Err(invalid("the access point port can not be 0".to_owned()));
+        }
+        config.ap_port = Some(port);
+    }
+
+    if let Some(ref dir) = network.tmp_dir {
+        check_tmp_dir(dir)?;
+        config.tmp_dir = dir.clone();
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::record::test::TempDir;
+
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn proxies_that_answer_are_accepted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        for scheme in ["http", "https", "socks4", "socks4a", "socks5", "socks5h"] {
+            let url = check_proxy(&format!("{}://127.0.0.1:{}", scheme, port)).await.unwrap();
+            assert_eq!(url.scheme(), scheme);
+        }
+    }
+
+    #[tokio::test]
+    async fn unreachable_proxies_are_refused() {
+        // a port that was just free, so nothing listens on it
+        let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+
+        let err = check_proxy(&format!("socks5://127.0.0.1:{}", port)).await.unwrap_err();
+        assert!(err.to_string().contains("unreachable"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn invalid_proxies_are_refused() {
+        let err = check_proxy("not a url").await.unwrap_err();
+        assert!(err.to_string().contains("not a valid proxy url"), "{}", err);
+
+        let err = check_proxy("ftp://127.0.0.1:21").await.unwrap_err();
+        assert!(err.to_string().contains("unknown proxy scheme 'ftp'"), "{}", err);
+    }
+
+    #[test]
+    fn tmp_dirs_are_created() {
+        let dir = TempDir::new();
+        let tmp = dir.0.join("nested").join("tmp");
+
+        check_tmp_dir(&tmp).unwrap();
+        assert!(tmp.is_dir());
+        assert_eq!(fs::read_dir(&tmp).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn unusable_tmp_dirs_are_refused() {
+        let dir = TempDir::new();
+        let file = dir.0.join("file");
+        fs::write(&file, b"").unwrap();
+
+        let err = check_tmp_dir(&file.join("tmp")).unwrap_err();
+        assert!(err.to_string().contains("can not use"), "{}", err);
+    }
+
+    fn rate(rate: &str) -> u32 {
+        parse_rate(rate).unwrap().get()
+    }
+
+    #[test]
+    fn rates_are_in_bytes_per_second() {
+        assert_eq!(rate("1"), 1);
+        assert_eq!(rate(" 800 "), 800);
+        assert_eq!(rate("800B/s"), 800);
+    }
+
+    #[test]
+    fn rates_take_binary_suffixes() {
+        assert_eq!(rate("800K"), 800 * 1024);
+        assert_eq!(rate("800k"), 800 * 1024);
+        assert_eq!(rate("2M"), 2 * 1024 * 1024);
+        assert_eq!(rate("1.5MB/s"), 3 * 512 * 1024);
+        assert_eq!(rate("3G"), 3 * 1024 * 1024 * 1024);
+        assert_eq!(rate("3 G"), 3 * 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn rates_below_a_byte_are_refused() {
+        for zero in ["0", "0K", "0.5", "-1M"] {
+            assert!(parse_rate(zero).is_err(), "{}", zero);
+        }
+    }
+
+    #[test]
+    fn rates_past_u32_are_refused() {
+        assert_eq!(rate("4294967295"), u32::MAX);
+        assert!(parse_rate("4294967296").is_err());
+        assert!(parse_rate("4G").is_err());
+        assert!(parse_rate("4194304K").is_err());
+    }
+
+    #[test]
+    fn garbage_rates_are_refused() {
+        for garbage in ["", "K", "fast", "2T", "2MM", "1e", "NaN", "inf"] {
+            let err = parse_rate(garbage).unwrap_err();
+            assert!(err.to_string().contains("is not a valid rate"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::Error;
+use crate::app::App;
+use crate::list::{format_duration, format_size};
+use crate::queue::{Job, Status};
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::cursor::Show;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, ListItem, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use std::io::{self, Stdout};
+use std::time::{Duration, Instant};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+// how much of the throughput estimate a new sample replaces
+const SMOOTHING: f64 = 0.3;
+
+/// Keeps an estimate of the download speed from how the fetched byte count changes.
+#[derive(Default)]
+struct Meter {
+    last: Option<(Instant, u64)>,
+    bytes_per_sec: f64,
+}
+
+impl Meter {
+    fn sample(&mut self, fetched: u64) {
+        let now = Instant::now();
+        if let Some((then, before)) = self.last {
+            let elapsed = now.duration_since(then).as_secs_f64();
+            if elapsed > 0.0 {
+                let rate = fetched.saturating_sub(before) as f64 / elapsed;
+                self.bytes_per_sec += SMOOTHING * (rate - self.bytes_per_sec);
+            }
+        }
+        self.last = Some((now, fetched));
+    }
+}
+
+/// What one frame of the dashboard shows.
+struct View {
+    title: String,
+    paused: bool,
+    jobs: Vec<Job>,
+    log: Vec<String>,
+    bytes_per_sec: f64,
+}
+
+impl View {
+    fn running(&self) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.status == Status::Running)
+    }
+
+    /// Time left for the running job, guessing the size of the tracks still to come from the
+    /// ones that are done.
+    fn eta(&self) -> Option<Duration> {
+        let job = self.running()?;
+        let progress = &job.progress;
+        if self.bytes_per_sec < 1.0 || progress.tracks == 0 {
+            return None;
+        }
+
+        let finished = progress.done + progress.failed + progress.skipped;
+        let track_size = match progress.done {
+            0 => progress.total as f64,
+            done => progress.bytes as f64 / done as f64,
+        };
+
+        let upcoming = progress.tracks.saturating_sub(finished + 1) as f64 * track_size;
+        let current = progress.total.saturating_sub(progress.fetched) as f64;
+        Some(Duration::from_secs_f64((upcoming + current) / self.bytes_per_sec))
+    }
+}
+
+/// Leaves raw mode and the alternate screen when dropped, however far entering them got.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+    }
+}
+
+/// Puts the terminal back the way it was, also when drawing fails.
+struct Screen(Terminal<CrosstermBackend<Stdout>>, RawMode);
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        // taken before anything else can fail, so the terminal is restored when it does
+        let raw_mode = RawMode::enable()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(Self(Terminal::new(CrosstermBackend::new(io::stdout()))?, raw_mode))
+    }
+}
+
+fn total_fetched(jobs: &[Job]) -> u64 {
+    jobs.iter().map(|job| job.progress.bytes + job.progress.fetched as u64).sum()
+}
+
+fn status_label(job: &Job) -> String {
+    match job.status {
+        Status::Waiting   => "waiting".to_owned(),
+        Status::Running   => format!("{}/{}", job.progress.track, job.progress.tracks),
+        Status::Done      => "done".to_owned(),
+        Status::Failed(_) => "failed".to_owned(),
+        Status::Cancelled => "cancelled".to_owned(),
+    }
+}
+
+fn render(frame: &mut Frame, view: &View) {
+    let [header, current, stats, queue, log, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(4),
+        Constraint::Length(3),
+        Constraint::Min(4),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ]).areas(frame.area());
+
+    let mut title = view.title.clone();
+    if view.paused {
+        title.push_str(" - paused");
+    }
+    frame.render_widget(Paragraph::new(title).bold().cyan(), header);
+
+    let block = Block::bordered().title(" downloading ");
+    match view.running() {
+        Some(job) if job.progress.track > 0 => {
+            let progress = &job.progress;
+            let ratio = progress.fetched as f64 / progress.total.max(1) as f64;
+            let label = format!(
+                "{} of {}",
+                format_size(progress.fetched as u64),
+                format_size(progress.total as u64),
+            );
+
+            let inner = block.inner(current);
+            frame.render_widget(block, current);
+
+            let [name, bar] = Layout::vertical([Constraint::Length(1), Constraint::Length(1)]).areas(inner);
+            let line = format!("#{} {} - track {}/{}: {}", job.id, job.name, progress.track, progress.tracks, progress.track_name);
+            frame.render_widget(Paragraph::new(line), name);
+            frame.render_widget(Gauge::default().ratio(ratio.min(1.0)).label(label).gauge_style(Style::new().fg(Color::Green)), bar);
+        },
+        Some(job) => frame.render_widget(Paragraph::new(format!("#{} {}: looking up tracks", job.id, job.name)).block(block), current),
+        None => frame.render_widget(Paragraph::new("nothing is downloading").block(block), current),
+    }
+
+    let (done, failed, skipped) = view.jobs.iter().fold((0, 0, 0), |(done, failed, skipped), job| {
+        (done + job.progress.done, failed + job.progress.failed, skipped + job.progress.skipped)
+    });
+    let eta = view.eta().map(|eta| format_duration(eta.as_millis() as u64)).unwrap_or_else(|| "-".to_owned());
+    let stats_line = Line::from(vec![
+        format!("{} done", done).green(),
+        "  ".into(),
+        format!("{} failed", failed).red(),
+        "  ".into(),
+        format!("{} skipped", skipped).yellow(),
+        format!("    {}/s    eta {}", format_size(view.bytes_per_sec as u64), eta).into(),
+    ]);
+    frame.render_widget(Paragraph::new(stats_line).block(Block::bordered().title(" tracks ")), stats);
+
+    let items: Vec<ListItem> = view.jobs.iter()
+        .map(|job| ListItem::new(format!("{:>4}  {:<10} {}", format!("#{}", job.id), status_label(job), job.name)))
+        .collect();
+    frame.render_widget(List::new(items).block(Block::bordered().title(" queue ")), queue);
+
+    let lines = log.height.saturating_sub(2) as usize;
+    let items: Vec<ListItem> = view.log.iter().skip(view.log.len().saturating_sub(lines)).map(|line| ListItem::new(line.as_str())).collect();
+    frame.render_widget(List::new(items).block(Block::bordered().title(" log ")), log);
+
+    frame.render_widget(Paragraph::new("q: leave   p: pause/resume   c: cancel the current download").dim(), footer);
+}
+
+/// Shows the queue full screen until the user leaves with q, or until it is empty if
+/// `until_idle` is set. Without a terminal to draw on it only waits, the queue prints what it
+/// does as it goes.
+pub async fn run(app: &App, until_idle: bool) -> Result<(), Error> {
+    let queue = app.queue();
+    if !console::Term::stdout().is_term() {
+        if until_idle {
+            queue.wait_idle().await;
+        }
+        return Ok(());
+    }
+
+    let mut screen = Screen::enter()?;
+    queue.set_muted(true);
+
+    let mut meter = Meter::default();
+    let title = format!("spotify-dl - {} ({})", app.session().username(), app.profile().name);
+
+    let result = loop {
+        let jobs = queue.jobs();
+        meter.sample(total_fetched(&jobs));
+
+        let view = View {
+            title: title.clone(),
+            paused: queue.is_paused(),
+            jobs,
+            log: queue.log(64),
+            bytes_per_sec: meter.bytes_per_sec,
+        };
+
+        if let Err(err) = screen.0.draw(|frame| render(frame, &view)) {
+            break Err(err);
+        }
+
+        if until_idle && queue.is_idle() {
+            break Ok(());
+        }
+
+        // waiting for input on this thread is fine, the downloads run on the runtime's workers
+        let key = match tokio::task::block_in_place(|| event::poll(REFRESH_INTERVAL)) {
+            Ok(true) => event::read(),
+            Ok(false) => continue,
+            Err(err) => break Err(err),
+        };
+
+        match key {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => break Ok(()),
+                KeyCode::Char('p') if view.paused => queue.resume(),
+                KeyCode::Char('p') => queue.pause(),
+                KeyCode::Char('c') => {
+                    if let Some(job) = view.running() {
+                        let _ = queue.cancel(job.id);
+                    }
+                },
+                _ => (),
+            },
+            Ok(_) => (),
+            Err(err) => break Err(err),
+        }
+    };
+
+    drop(screen);
+    queue.set_muted(false);
+    Ok(result?)
+}
@@ -0,0 +1,99 @@
+use crate::Error;
+use crate::error::{Context, Step};
+use crate::record::Recorder;
+
+use crate::spotify::Session;
+
+use librespot::audio::{AudioDecrypt, AudioFetchParams, AudioFile};
+use librespot::core::FileId;
+use librespot::metadata::audio::AudioFileFormat;
+use librespot::metadata::Track;
+use librespot::playback::player::SPOTIFY_OGG_HEADER_END;
+
+use std::io::{self, Seek, SeekFrom};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::time::Duration;
+
+// the files are written as-is, so only ogg/vorbis formats are usable, best first
+const FORMATS: [AudioFileFormat; 3] = [
+    AudioFileFormat::OGG_VORBIS_320,
+    AudioFileFormat::OGG_VORBIS_160,
+    AudioFileFormat::OGG_VORBIS_96,
+];
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+pub fn stream_data_rate(format: AudioFileFormat) -> usize {
+    let kbps = match format {
+        AudioFileFormat::OGG_VORBIS_96 => 12,
+        AudioFileFormat::OGG_VORBIS_160 => 20,
+        _ => 40,
+    };
+
+    kbps * 1024
+}
+
+/// Picks the best quality file of `track` that can be written out as-is.
+pub fn select_file(track: &Track) -> Option<(AudioFileFormat, FileId)> {
+    FORMATS.iter().find_map(|format| track.files.get(format).map(|id| (*format, *id)))
+}
+
+/// Tunes the fetch layer for pulling whole files rather than real-time playback, fetching at
+/// most `max_rate` bytes per second over all downloads. Must be called before the first
+/// download, the parameters can only be set once.
+pub fn install_fetch_params(max_rate: Option<NonZeroU32>) {
+    let _ = AudioFetchParams::set(AudioFetchParams {
+        max_bytes_per_second: max_rate,
+        ..AudioFetchParams::bulk()
+    });
+}
+
+/// Downloads the audio file for `metadata` straight from the CDN, decrypts it, and writes it to
+/// `path` with metadata tags. `on_progress` is called periodically with the number of bytes
+/// fetched so far and the total file size.
+pub async fn download_track<F>(metadata: &Track, session: &Session, path: &Path, on_progress: F) -> Result<String, Error>
+where
+    F: Fn(usize, usize),
+{
+    let track = metadata.id;
+    if path.exists() {
+        return Err(Error::Exists(track));
+    }
+
+    let (format, file_id) = select_file(metadata).ok_or(Error::Unavailable(track))?;
+
+    let file = AudioFile::open(session, file_id, stream_data_rate(format)).await.during(track, Step::OpenFile)?;
+    let controller = file.get_stream_loader_controller().during(track, Step::OpenFile)?;
+
+    // without the key the file could only be written still encrypted
+    let key = session.audio_key().request(track, file_id).await.during(track, Step::OpenFile)?;
+
+    let mut recorder = Recorder::create(path, metadata).during(track, Step::Write)?;
+    let name = metadata.name.clone();
+
+    // how far ahead of the reader data is fetched is governed by the bulk fetch params
+    controller.set_stream_mode();
+
+    let mut copy = tokio::task::spawn_blocking(move || -> Result<(), Error> {
+        let mut decrypted = AudioDecrypt::new(Some(key), file);
+        decrypted.seek(SeekFrom::Start(SPOTIFY_OGG_HEADER_END))?;
+        io::copy(&mut decrypted, &mut recorder)?;
+
+        recorder.finish()
+    });
+
+    loop {
+        tokio::select! {
+            result = &mut copy => {
+                result.map_err(|err| Error::Io(io::Error::other(err))).and_then(|r| r).during(track, Step::Write)?;
+                break;
+            },
+            _ = tokio::time::sleep(PROGRESS_INTERVAL) => {
+                on_progress(controller.downloaded_len(), controller.len());
+            },
+        }
+    }
+
+    Ok(name)
+}
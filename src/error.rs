@@ -1,46 +1,172 @@
 use crate::spotify::SpotifyId;
 
+use librespot::oauth::OAuthError;
+
 use std::fmt::{Display, Formatter};
 
 #[derive(Debug)]
 pub enum Error {
-    Empty,
     Exists(SpotifyId),
-    EarlyPause,
     Unavailable(SpotifyId),
     InvalidLink(String),
+    MissingArgument(&'static str),
+    InvalidArgument(String),
+    UnknownCommand(String),
+    Login(String),
+    Credentials(String),
+    Profile(String),
+    Config(String),
+    Queue(String),
+
+    /// A radio station was asked for with something other than a track, artist, album or playlist.
+    NoRadio(SpotifyId),
+
+    /// The user cancelled a download.
+    Cancelled,
+    Ffmpeg(String),
+    Librespot(librespot::core::Error),
+    Io(std::io::Error),
+
+    /// An error that happened while working on a specific track.
+    Track {
+        id: SpotifyId,
+        step: Step,
+        source: Box<Error>,
+    },
+}
+
+/// The part of downloading a track that failed.
+#[derive(Debug, Clone, Copy)]
+pub enum Step {
+    Metadata,
+    OpenFile,
+    Write,
+    Export,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str(match self {
+            Self::Metadata => "fetching metadata",
+            Self::OpenFile => "opening audio file",
+            Self::Write    => "writing audio file",
+            Self::Export   => "exporting",
+        })
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
         match self {
             Self::Exists(id) => {
-                write!(f, "{} already exists", id.to_base62().unwrap())?;
+                write!(f, "{} already exists", id.to_base62().unwrap_or_default())?;
             },
             Self::Unavailable(id) => {
-				write!(f, "the requested resource is unavailable {}", id.to_base62().unwrap())?;
+                write!(f, "the requested resource is unavailable {}", id.to_base62().unwrap_or_default())?;
             },
             Self::InvalidLink(ref link) => {
                 f.write_str("tried to parse an invalid spotify link: ")?;
                 f.write_str(link)?;
-                f.write_str("\n")?;
             },
-            _ => f.write_str("unspecified error")?,
+            Self::MissingArgument(usage) => {
+                write!(f, "missing argument, usage: {}", usage)?;
+            },
+            Self::InvalidArgument(ref reason) => {
+                write!(f, "invalid argument: {}", reason)?;
+            },
+            Self::UnknownCommand(ref cmd) => {
+                write!(f, "unknown command '{}', type 'help' for a list of commands", cmd)?;
+            },
+            Self::Login(ref reason) => {
+                write!(f, "failed to log in: {}", reason)?;
+            },
+            Self::Credentials(ref reason) => {
+                write!(f, "stored credentials: {}", reason)?;
+            },
+            Self::Profile(ref reason) => {
+                f.write_str(reason)?;
+            },
+            Self::Config(ref reason) => {
+                write!(f, "invalid configuration: {}", reason)?;
+            },
+            Self::Queue(ref reason) => {
+                f.write_str(reason)?;
+            },
+            Self::NoRadio(id) => {
+                let kind: &str = id.item_type.into();
+                write!(f, "radio needs a track, artist, album or playlist, not a {}", kind)?;
+            },
+            Self::Cancelled => {
+                f.write_str("cancelled")?;
+            },
+            Self::Ffmpeg(ref reason) => {
+                write!(f, "ffmpeg failed: {}", reason)?;
+            },
+            Self::Librespot(ref err) => {
+                write!(f, "{}", err)?;
+            },
+            Self::Io(ref err) => {
+                write!(f, "io error: {}", err)?;
+            },
+            Self::Track { id, step, ref source } => {
+                write!(f, "{} failed while {}: {}", id.to_base62().unwrap_or_default(), step, source)?;
+            },
         };
 
         Ok(())
     }
 }
 
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Librespot(ref err) => Some(err),
+            Self::Io(ref err) => Some(err),
+            Self::Track { ref source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl Error {
     pub fn invalid_link(input: &str) -> Self {
         Self::InvalidLink(input.to_owned())
     }
+
+    /// Attaches the track and step an error happened in, unless it already has them.
+    pub fn during(self, id: SpotifyId, step: Step) -> Self {
+        match self {
+            Self::Track { .. } | Self::Exists(_) | Self::Unavailable(_) => self,
+            _ => Self::Track { id, step, source: Box::new(self) },
+        }
+    }
+}
+
+/// Shorthand for `map_err(|e| Error::from(e).during(id, step))`.
+pub trait Context<T> {
+    fn during(self, id: SpotifyId, step: Step) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn during(self, id: SpotifyId, step: Step) -> Result<T, Error> {
+        self.map_err(|err| err.into().during(id, step))
+    }
+}
+
+impl From<std::io::Error> for Error {
+	fn from(err: std::io::Error) -> Self {
+    	Self::Io(err)
+	}
+}
+
+impl From<librespot::core::Error> for Error {
+	fn from(err: librespot::core::Error) -> Self {
+    	Self::Librespot(err)
+	}
 }
 
-impl From<()> for Error {
-	fn from(_: ()) -> Self {
-    	Self::Empty
+impl From<OAuthError> for Error {
+	fn from(err: OAuthError) -> Self {
+    	Self::Login(err.to_string())
 	}
 }
@@ -0,0 +1,117 @@
+use crate::Error;
+use crate::availability::{self, Availability};
+use crate::download;
+use crate::record::Recorder;
+use crate::spotify;
+
+use crate::spotify::Session;
+use crate::spotify::SpotifyId;
+
+use console::{pad_str, Alignment};
+
+use std::path::Path;
+
+const TITLE_WIDTH: usize = 32;
+const ARTISTS_WIDTH: usize = 24;
+const STATUS_WIDTH: usize = 12;
+
+pub fn format_duration(ms: u64) -> String {
+    let seconds = ms / 1000;
+    let (hours, minutes, seconds) = (seconds / 3600, (seconds / 60) % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 30 => format!("{:.1} GiB", b as f64 / (1u64 << 30) as f64),
+        b if b >= 1 << 20 => format!("{:.1} MiB", b as f64 / (1u64 << 20) as f64),
+        b => format!("{:.1} KiB", b as f64 / (1u64 << 10) as f64),
+    }
+}
+
+/// Prints what `id` resolves to without downloading anything: one row per track with its
+/// availability and whether it is already on disk, followed by totals.
+pub async fn list(id: SpotifyId, session: &Session) -> Result<(), Error> {
+    let (name, tracks) = spotify::get_tracks_to_download(id, session).await?;
+    let metadata = spotify::get_tracks_metadata(&tracks, session).await;
+
+    println!("found {} with {} tracks", name, tracks.len());
+
+    println!();
+    println!(
+        "{:>4}  {}  {}  {:>8}  {}  local",
+        "#",
+        pad_str("title", TITLE_WIDTH, Alignment::Left, None),
+        pad_str("artists", ARTISTS_WIDTH, Alignment::Left, None),
+        "duration",
+        pad_str("status", STATUS_WIDTH, Alignment::Left, None),
+    );
+
+    let mut total_ms = 0;
+    let mut download_bytes = 0;
+    let (mut local_count, mut unavailable_count) = (0, 0);
+
+    for (i, (id, result)) in tracks.iter().zip(metadata).enumerate() {
+        let exists = Path::new(&id.to_base62()?).with_extension("ogg").exists();
+        if exists {
+            local_count += 1;
+        }
+
+        let track = match result {
+            Ok(track) => track,
+            Err(err) => {
+                unavailable_count += 1;
+                println!("{:>4}  {}", i + 1, console::style(err).for_stdout().red());
+                continue;
+            }
+        };
+
+        let duration_ms = track.duration.max(0) as u64;
+        total_ms += duration_ms;
+
+        let availability = availability::check(&track, session).await;
+        if availability.is_downloadable() && !exists {
+            let source = match availability {
+                Availability::Alternative(ref alternative) => alternative.as_ref(),
+                _ => &track,
+            };
+            if let Some((format, _)) = download::select_file(source) {
+                download_bytes += duration_ms * download::stream_data_rate(format) as u64 / 1000;
+            }
+        }
+
+        let available = availability.is_downloadable();
+        let status = availability.describe();
+        let status = console::style(pad_str(status, STATUS_WIDTH, Alignment::Left, None)).for_stdout();
+        let status = if available {
+            status.green()
+        } else {
+            unavailable_count += 1;
+            status.red()
+        };
+
+        let artists = Recorder::get_artists_string(&track);
+        println!(
+            "{:>4}  {}  {}  {:>8}  {}  {}",
+            i + 1,
+            pad_str(&track.name, TITLE_WIDTH, Alignment::Left, Some("…")),
+            pad_str(&artists, ARTISTS_WIDTH, Alignment::Left, Some("…")),
+            format_duration(duration_ms),
+            status,
+            if exists { "yes" } else { "no" },
+        );
+    }
+
+    println!();
+    println!("{} tracks, {} total", tracks.len(), format_duration(total_ms));
+    println!("{} already downloaded, {} unavailable", local_count, unavailable_count);
+    println!("about {} left to download", format_size(download_bytes));
+    println!();
+
+    Ok(())
+}
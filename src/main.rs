@@ -1,33 +1,38 @@
 mod record;
 mod spotify;
+mod auth;
+mod availability;
+mod download;
+mod list;
 mod error;
-
+mod store;
+mod profile;
+mod config;
+mod search;
+mod repl;
+mod app;
+mod queue;
+mod dashboard;
+
+use app::App;
 use error::Error;
-use record::RecordSink;
+use profile::{Profile, Profiles};
+use config::Network;
+use queue::{Status, Target};
 
-use spotify::SessionConfig;
 use spotify::Session;
-use spotify::SpotifyId;
-
-
-use librespot::metadata::{
-    Metadata,
-    Track,
-};
 
-use sanitise_file_name::sanitise;
 use rustyline::error::ReadlineError;
-use rustyline::{DefaultEditor};
 
 use console::Style;
 use console::Term;
 
-use tokio;
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use lazy_static::lazy_static;
+use sanitise_file_name::sanitise;
 
 lazy_static! {
     pub static ref terminal: Term = Term::stdout();
@@ -36,22 +41,21 @@ lazy_static! {
 
 // pub static mut CREDS: Option<spotify::Credentials> = None;
 
-fn enter_working_directory() {
-    let home = std::env::home_dir().expect("could not find home dir");
-    let workdir = home.join("Music/spotify-dl");
-    std::fs::create_dir_all(&workdir).unwrap();
-    std::env::set_current_dir(&workdir).unwrap();
+fn enter_working_directory(workdir: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(workdir)?;
+    std::env::set_current_dir(workdir)?;
+    Ok(())
 }
 
-pub fn print_session_header(session: &Session) {
+pub fn print_session_header(session: &Session, profile: &Profile) {
     let version_major = env!("CARGO_PKG_VERSION_MAJOR");
     let version_minor = env!("CARGO_PKG_VERSION_MINOR");
 
     println!("Spotify-DL");
     println!("----------");
-    println!("version: {}", format!("{}.{}", version_major, version_minor));
-    println!("logged in as {}", highlight.apply_to(session.username()));
-    println!("will download songs to {}", highlight.apply_to(std::env::current_dir().unwrap().display()));
+    println!("version: {}.{}", version_major, version_minor);
+    println!("logged in as {} (profile {})", highlight.apply_to(session.username()), highlight.apply_to(&profile.name));
+    println!("will download songs to {}", highlight.apply_to(std::env::current_dir().unwrap_or_default().display()));
     println!("paste spotify links below to download them, or type 'help' for more options");
     println!();
 }
@@ -62,113 +66,433 @@ pub fn usage() {
     println!("--------");
     println!("download <link>      - download the contents of the Playlist, Album or Track");
     println!("export <path> <link> - download the contents of <link> and then copy them to <path>");
+    println!("list <link>          - show what <link> contains without downloading it");
+    println!("                       (also 'download --dry-run <link>')");
+    println!("search <query>       - find tracks, albums, artists and playlists and pick");
+    println!("                       which ones to download");
+    println!("radio <link> [--count N]");
+    println!("                     - download a radio station made from a track, artist, album or");
+    println!("                       playlist into a folder named after it (50 tracks by default)");
+    println!("whoami               - show the logged in account");
+    println!("logout               - wipe the stored credentials and quit");
+    println!("profile list         - show all profiles");
+    println!("profile add <name> [--headless] [library]");
+    println!("                     - add a profile with its own login and download folder");
+    println!("profile remove <name> - remove a profile and wipe its credentials");
+    println!("profile use <name>   - log in with another profile and make it the default");
+    println!("queue                - show the queued downloads");
+    println!("status               - show what is being downloaded right now");
+    println!("pause / resume       - stop the queue after the current track, or continue it");
+    println!("cancel <id>          - cancel a queued or running download");
+    println!("clear                - drop all waiting and finished downloads from the queue");
+    println!("dashboard            - watch the queue full screen, with progress and speed");
     println!("help                 - print this message");
     println!();
 }
 
-async fn download(id: SpotifyId, session: &Session, export_path: Option<&Path>) -> Result<(), Error> {
-    let checkmark = console::style("✔".to_string()).for_stdout().green();
-    let error     = console::style("✘".to_string()).for_stdout().red();
-    let dot       = console::style("·".to_string()).for_stdout().yellow().bright();
+/// What the REPL should do after a command has been handled.
+enum Flow {
+    Continue,
+    Quit,
+}
+
+const PROFILE_USAGE: &str = "profile list | add <name> [--headless] [library] | remove <name> | use <name>";
 
-    if let Some(p) = export_path {
-        std::fs::create_dir_all(p).expect("failed to create export directory");
+fn print_profiles(profiles: &Profiles, active: &str) -> Result<(), Error> {
+    println!();
+    for name in profiles.names() {
+        let profile = profiles.get(name)?;
+        let marker = if name == active { "*" } else { " " };
+        let status = if profile.store()?.exists() { "logged in" } else { "not logged in" };
+        println!("{} {:<16} {:<14} {}", marker, name, status, profile.preferences.library.display());
     }
+    println!();
+    Ok(())
+}
 
-    let tracks = spotify::get_tracks_to_download(id, session);
+/// Logs in with `name`'s credentials and moves into its library, keeping the current profile if
+/// that fails.
+async fn switch_profile(name: &str, profiles: &mut Profiles, app: &App) -> Result<(), Error> {
+    if !app.queue().is_idle() {
+        return Err(Error::Queue("wait for the queue to finish, or clear it, before switching profiles".to_owned()));
+    }
 
-    let size = tracks.len();
-	for (mut i, track_id) in tracks.into_iter().enumerate() {
-    	i += 1;
-    	let path = Path::new(&track_id.to_base62().unwrap()).with_extension("ogg");
-    	if path.exists() {
-        	println!("{} ({:02}/{:02}) {} : exists", checkmark, i, size, &track_id.to_base62().unwrap());
-    	}
+    let profile = profiles.get(name)?;
+    let mut store = profile.store()?;
+    let config = config::session_config(&profile.preferences.network.with(app.network())).await?;
 
-    	else {
-        	println!("{} ({:02}/{:02}) {}", dot, i, size, &track_id.to_base62().unwrap());
-    		let res = spotify::record_track(track_id, session.clone()).await;
+    let previous = env::current_dir()?;
+    enter_working_directory(&profile.preferences.library)?;
 
-    		match res {
-        		Ok(name) => {
-            		terminal.clear_last_lines(1).unwrap();
-                	println!("{} ({:02}/{:02}) {} : {}", checkmark, i, size, &track_id.to_base62().unwrap(), name);
-        		},
+    let session = Session::new(config, None);
+    let options = auth::LoginOptions { headless: profile.preferences.headless, ..Default::default() };
+    if let Err(err) = auth::connect(&session, &mut store, &options).await {
+        let _ = env::set_current_dir(previous);
+        return Err(err);
+    }
 
-        		Err(message) => {
-            		terminal.clear_last_lines(1).unwrap();
-                	println!("{} ({:02}/{:02}) {} : {}", error, i, size, &track_id.to_base62().unwrap(), message);
-        		},
-    		}
-    	}
+    profiles.set_active(name)?;
+    print_session_header(&session, &profile);
+    app.switch(session, store, profile).await;
+    Ok(())
+}
 
-    	if let Some(p) = export_path {
-        	let metadata = Track::get(session, &track_id).await.unwrap();
-        	let dest = p.join(sanitise(&metadata.name)).with_extension("ogg");
+async fn profile_command(args: &[&str], app: &App) -> Result<(), Error> {
+    let mut profiles = Profiles::load()?;
 
-        	println!("exporting {}", dest.display());
-			if std::fs::copy(path, dest).is_err() {
-    			println!("copy failed");
-			}
-			println!();
-    	}
+    match args {
+        [] | ["list"] => print_profiles(&profiles, &app.profile().name)?,
 
-	}
+        ["add", name, rest @ ..] => {
+            let headless = rest.contains(&"--headless");
+            let library = rest.iter().find(|arg| **arg != "--headless")
+                .map(|path| env::current_dir().unwrap_or_default().join(path));
 
-	println!();
-	Ok(())
+            profiles.add(name, library, headless)?;
+            println!("added profile {}, switch to it with 'profile use {}'", name, name);
+        },
+
+        ["remove", name] => {
+            if *name == app.profile().name {
+                return Err(Error::Profile("switch to another profile before removing this one".to_owned()));
+            }
+            profiles.remove(name)?;
+            println!("removed profile {}", name);
+        },
+
+        ["use", name] => switch_profile(name, &mut profiles, app).await?,
+
+        _ => return Err(Error::MissingArgument(PROFILE_USAGE)),
+    }
+
+    Ok(())
 }
 
-async fn handle_command(cmd: String, ctx: &Session) -> Result<(), Error> {
-    let mut iter = cmd.split(" ");
+fn print_queue(app: &App) {
+    let jobs = app.queue().jobs();
+    if jobs.is_empty() {
+        println!("the queue is empty");
+        return;
+    }
+
+    println!();
+    for job in jobs {
+        let status = match job.status {
+            Status::Waiting      => "waiting".to_owned(),
+            Status::Running      => format!("{}/{}", job.progress.track, job.progress.tracks),
+            Status::Done         => "done".to_owned(),
+            Status::Failed(_)    => "failed".to_owned(),
+            Status::Cancelled    => "cancelled".to_owned(),
+        };
+        println!("{:>4}  {:<10} {}", format!("#{}", job.id), status, job.name);
+    }
+    if app.queue().is_paused() {
+        println!("the queue is paused, 'resume' continues it");
+    }
+    println!();
+}
 
-    match iter.next().ok_or(Error::Empty)? {
-        "" => return Ok(()),
+fn print_status(app: &App) {
+    let jobs = app.queue().jobs();
+    let waiting = jobs.iter().filter(|job| job.status == Status::Waiting).count();
+
+    match jobs.iter().find(|job| job.status == Status::Running) {
+        Some(job) => {
+            let progress = &job.progress;
+            println!("#{} {}", job.id, job.name);
+            if progress.track > 0 {
+                let percent = progress.fetched * 100 / progress.total.max(1);
+                println!("  track {}/{}: {} ({}%)", progress.track, progress.tracks, progress.track_name, percent);
+            }
+            println!("  {} done, {} failed, {} skipped", progress.done, progress.failed, progress.skipped);
+        },
+        None => println!("nothing is downloading"),
+    }
+
+    if waiting > 0 {
+        println!("{} more waiting", waiting);
+    }
+    if app.queue().is_paused() {
+        println!("the queue is paused, 'resume' continues it");
+    }
+}
+
+fn enqueue(app: &App, link: &str, export: Option<PathBuf>) -> Result<(), Error> {
+    let id = spotify::parse_link(link)?;
+    let job = app.queue().push(Target::Link(id), export);
+    println!("queued #{}", job);
+    Ok(())
+}
+
+/// Lets the track being written finish, so quitting doesn't leave a broken file behind.
+async fn stop_queue(app: &App) {
+    if app.queue().is_idle() {
+        return;
+    }
+
+    println!("finishing the current track, the rest of the queue is dropped");
+    app.queue().pause();
+    app.queue().wait_track_done().await;
+}
+
+async fn handle_command(cmd: String, app: &App) -> Result<Flow, Error> {
+    let mut iter = cmd.split_whitespace();
+
+    let command = iter.next().unwrap_or_default();
+    if !command.is_empty() && app.session().is_invalid() {
+        app.reconnect().await?;
+    }
+    let ctx = &app.session();
+
+    match command {
+        "" => (),
         "h" | "?" | "help" => usage(),
-        "q" | "quit" | "exit" => return Err(Error::Empty),
+        "q" | "quit" | "exit" => return Ok(Flow::Quit),
+
+        "whoami" => auth::whoami(ctx, &*app.store().await).await,
+
+        "profile" | "profiles" => {
+            let args: Vec<&str> = iter.collect();
+            profile_command(&args, app).await?;
+        },
 
         "logout" => {
-            let _ = std::fs::remove_file("access_token.txt");
-            return Err(Error::Empty);
+            stop_queue(app).await;
+            auth::logout(&mut *app.store().await)?;
+            println!("logged out, the stored credentials have been wiped");
+            return Ok(Flow::Quit);
+        },
+
+        "queue" => print_queue(app),
+        "status" => print_status(app),
+
+        "pause" => {
+            app.queue().pause();
+            println!("the queue will pause after the current track");
+        },
+
+        "resume" => {
+            app.queue().resume();
+            println!("resumed");
+        },
+
+        "cancel" => {
+            let arg = iter.next().ok_or(Error::MissingArgument("cancel <id>"))?;
+            let id = arg.trim_start_matches('#').parse()
+                .map_err(|_| Error::Queue(format!("'{}' is not a job id, see 'queue'", arg)))?;
+
+            if app.queue().cancel(id)? {
+                println!("#{} will stop after the current track", id);
+            } else {
+                println!("#{} cancelled", id);
+            }
+        },
+
+        "dashboard" | "watch" => dashboard::run(app, false).await?,
+
+        "clear" => {
+            let dropped = app.queue().clear();
+            println!("dropped {} jobs from the queue", dropped);
         },
 
         "d" | "download" => {
-            let arg = iter.next().expect("no arg after download command");
-            let id = spotify::parse_link(arg).expect("invalid spoitfy link");
-            let result = download(id, ctx, None).await;
-            return result
+            let mut arg = iter.next().ok_or(Error::MissingArgument("download [--dry-run] <link>"))?;
+            let dry_run = arg == "--dry-run";
+            if dry_run {
+                arg = iter.next().ok_or(Error::MissingArgument("download [--dry-run] <link>"))?;
+            }
+
+            if dry_run {
+                list::list(spotify::parse_link(arg)?, ctx).await?;
+            } else {
+                enqueue(app, arg, None)?;
+            }
+        },
+
+        "l" | "list" => {
+            let arg = iter.next().ok_or(Error::MissingArgument("list <link>"))?;
+            let id = spotify::parse_link(arg)?;
+            list::list(id, ctx).await?;
         },
 
         "e" | "export" => {
-            let path = iter.next().expect("export requires 2 arguments");
-            let link = iter.next().expect("export requires 2 arguments");
-            let id = spotify::parse_link(link).expect("invalid spoitfy link");
-            download(id, ctx, Some(Path::new(&path))).await?;
+            let path = iter.next().ok_or(Error::MissingArgument("export <path> <link>"))?;
+            let link = iter.next().ok_or(Error::MissingArgument("export <path> <link>"))?;
+            enqueue(app, link, Some(env::current_dir()?.join(path)))?;
         },
 
-        arg => {
-            let id = spotify::parse_link(arg)?;
-            return download(id, ctx, None).await;
-        }
+        "r" | "radio" => {
+            const USAGE: &str = "radio <link> [--count N]";
+            let mut link = None;
+            let mut count = spotify::RADIO_SIZE;
+            while let Some(arg) = iter.next() {
+                if arg == "--count" {
+                    let n = iter.next().ok_or(Error::MissingArgument(USAGE))?;
+                    count = n.parse().ok().filter(|n| *n > 0)
+                        .ok_or_else(|| Error::InvalidArgument(format!("'{}' is not a track count, usage: {}", n, USAGE)))?;
+                } else {
+                    link = Some(arg);
+                }
+            }
+
+            let seed = spotify::parse_link(link.ok_or(Error::MissingArgument(USAGE))?)?;
+            let (name, ids) = spotify::get_radio_tracks(seed, count, ctx).await?;
+            println!("found {} with {} tracks", highlight.apply_to(&name), ids.len());
+
+            // the tracks are exported to a folder named after the station, so they can be played as one
+            let export = env::current_dir()?.join(sanitise(&name));
+            let job = app.queue().push(Target::Tracks { name, ids }, Some(export.clone()));
+            println!("queued #{}, exporting to {}", job, export.display());
+        },
+
+        "s" | "search" => {
+            let query = iter.collect::<Vec<_>>().join(" ");
+            if query.is_empty() {
+                return Err(Error::MissingArgument("search <query>"));
+            }
+
+            let results = search::search(&query, ctx).await?;
+            if results.is_empty() {
+                println!("nothing found for '{}'", query);
+                return Ok(Flow::Continue);
+            }
+
+            for id in search::choose(&results)? {
+                let job = app.queue().push(Target::Link(id), None);
+                println!("queued #{}", job);
+            }
+        },
+
+        arg if arg.contains("open.spotify.com") => enqueue(app, arg, None)?,
+
+        arg => return Err(Error::UnknownCommand(arg.to_owned())),
     }
 
-    Ok(())
+    Ok(Flow::Continue)
 }
 
-fn test_ffmpeg() -> Result<(), ()> {
-    let output = Command::new("ffmpeg").arg("-version").output().map_err(|_| ())?;
+fn test_ffmpeg() -> Result<(), Error> {
+    let output = Command::new("ffmpeg").arg("-version").output()
+        .map_err(|err| Error::Ffmpeg(err.to_string()))?;
 
-    if output.status.code() == Some(0) {
+    if output.status.success() {
         Ok(())
     } else {
-        Err(())
+        Err(Error::Ffmpeg(format!("ffmpeg -version exited with {}", output.status)))
     }
 }
 
+fn print_cli_usage(program: &str, opts: &getopts::Options) {
+    let brief = format!("Usage: {} [options] [link...]\n\nDownloads the given links and exits, or starts an interactive prompt if there are none.", program);
+    print!("{}", opts.usage(&brief));
+}
+
 #[tokio::main]
 async fn main() {
-	terminal.clear_screen().unwrap();
-	enter_working_directory();
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = getopts::Options::new();
+    opts.optflag("h", "help", "print this help message");
+    opts.optflag("n", "dry-run", "list what the links contain instead of downloading them");
+    opts.optflag("", "headless", "log in without a browser by pasting the redirect URL back in");
+    opts.optopt("", "import-credentials", "log in with a credentials.json from another machine", "FILE");
+    opts.optflag("", "encrypt", "encrypt the stored credentials with a passphrase");
+    opts.optflag("", "logout", "wipe the stored credentials and exit");
+    opts.optopt("p", "profile", "use the credentials and download folder of this profile", "NAME");
+    opts.optopt("", "proxy", "connect through this http(s):// or socks4(a)://, socks5(h):// proxy", "URL");
+    opts.optopt("", "ap-port", "only use access points on this port, e.g. 443 behind a firewall", "PORT");
+    opts.optopt("", "tmp-dir", "keep partially downloaded files in this directory", "DIR");
+    opts.optopt("", "max-rate", "download at most this many bytes per second in total, e.g. 800K or 2M", "RATE");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(matches) => matches,
+        Err(err) => {
+            println!("{}", err);
+            print_cli_usage(&args[0], &opts);
+            return;
+        },
+    };
+
+    if matches.opt_present("help") {
+        print_cli_usage(&args[0], &opts);
+        return;
+    }
+
+    // relative to where spotify-dl was started, not the download directory
+    let import = matches.opt_str("import-credentials")
+        .map(|path| env::current_dir().unwrap_or_default().join(path));
+
+    let ap_port = match matches.opt_get::<u16>("ap-port") {
+        Ok(port) => port,
+        Err(err) => {
+            println!("invalid --ap-port: {}", err);
+            return;
+        },
+    };
+
+    let network = Network {
+        proxy: matches.opt_str("proxy"),
+        ap_port,
+        tmp_dir: matches.opt_str("tmp-dir").map(|dir| env::current_dir().unwrap_or_default().join(dir)),
+        max_rate: matches.opt_str("max-rate"),
+    };
+
+	let _ = terminal.clear_screen();
+
+    let profile = match Profiles::load() {
+        Ok(profiles) => {
+            let name = matches.opt_str("profile").unwrap_or_else(|| profiles.active().to_owned());
+            profiles.get(&name)
+        },
+        Err(err) => Err(err),
+    };
+
+    let profile = match profile {
+        Ok(profile) => profile,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        },
+    };
+
+	if let Err(err) = enter_working_directory(&profile.preferences.library) {
+    	println!("could not enter the download directory: {}", err);
+    	return;
+	}
+
+    let mut store = match profile.store() {
+        Ok(store) => store,
+        Err(err) => {
+            println!("could not open the credentials store: {}", err);
+            return;
+        },
+    };
+
+    if matches.opt_present("logout") {
+        match auth::logout(&mut store) {
+            Ok(()) => println!("logged out, the stored credentials have been wiped"),
+            Err(err) => println!("{}", err),
+        }
+        return;
+    }
+
+    let settings = profile.preferences.network.with(&network);
+    let config = match config::session_config(&settings).await {
+        Ok(config) => config,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        },
+    };
+
+    // the fetch parameters can only be set once, so later profile switches keep this rate
+    let max_rate = match config::max_rate(&settings) {
+        Ok(rate) => rate,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        },
+    };
 
     if test_ffmpeg().is_err() {
         println!("ffmpeg is not installed properly, please fix that by installing it from here:");
@@ -177,20 +501,75 @@ async fn main() {
         return;
     }
 
-    let session = Session::new(SessionConfig::default(), None);
-    let _creds = spotify::connect(&session).expect("failed to log in");
+    download::install_fetch_params(max_rate);
+    spotify::install_metadata_cache();
+
+    let session = Session::new(config, None);
+    let login = match import {
+        Some(path) => auth::import(&session, &store, &path).await,
+        None => {
+            let options = auth::LoginOptions {
+                headless: matches.opt_present("headless") || profile.preferences.headless,
+                encrypt: matches.opt_present("encrypt"),
+            };
+            auth::connect(&session, &mut store, &options).await
+        },
+    };
 
-    print_session_header(&session);
+    if let Err(err) = login {
+        println!("{}", err);
+        if let Error::Credentials(_) = err {
+            println!("start spotify-dl with --logout to remove them and log in again");
+        }
+        return;
+    }
+
+    print_session_header(&session, &profile);
+    let app = App::new(session, store, profile, network);
+    queue::spawn_worker(app.clone());
+
+    if !matches.free.is_empty() {
+        let command = if matches.opt_present("dry-run") { "list" } else { "download" };
+        for link in &matches.free {
+            if let Err(err) = handle_command(format!("{} {}", command, link), &app).await {
+                println!("{} {}", console::style("error:").for_stdout().red(), err);
+            }
+        }
+        if let Err(err) = dashboard::run(&app, true).await {
+            println!("{} {}", console::style("error:").for_stdout().red(), err);
+            app.queue().wait_idle().await;
+        }
+        return;
+    }
 
-	let mut rl = DefaultEditor::new().unwrap();
+	let mut rl = match repl::editor() {
+    	Ok(rl) => rl,
+    	Err(err) => {
+        	println!("could not start the prompt: {}", err);
+        	return;
+    	},
+	};
+
+    if let Ok(printer) = rl.create_external_printer() {
+        app.queue().set_printer(Box::new(printer));
+    }
 
     loop {
         let readline = rl.readline("> ");
         match readline {
             Ok(line) => {
-                rl.add_history_entry(line.as_str()).unwrap();
-                if handle_command(line, &session).await.is_err() {
-                    break;
+                let _ = rl.add_history_entry(line.as_str());
+                if let Some(path) = repl::history_path() {
+                    let _ = rl.append_history(&path);
+                }
+                if let Some(helper) = rl.helper_mut() {
+                    helper.remember(&line);
+                }
+
+                match handle_command(line, &app).await {
+                    Ok(Flow::Continue) => (),
+                    Ok(Flow::Quit) => break,
+                    Err(err) => println!("{} {}", console::style("error:").for_stdout().red(), err),
                 }
             },
             Err(ReadlineError::Interrupted) => {
@@ -207,4 +586,6 @@ async fn main() {
             }
         }
     }
+
+    stop_queue(&app).await;
 }
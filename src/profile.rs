@@ -0,0 +1,146 @@
+use crate::Error;
+use crate::config::Network;
+use crate::store::{self, CredentialStore};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+const PROFILES_FILE: &str = "profiles.json";
+const PROFILES_DIR: &str = "profiles";
+
+/// The profile that exists without being added, it keeps the locations older versions used.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Settings that belong to a single account.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Preferences {
+    /// Where tracks are downloaded to.
+    pub library: PathBuf,
+
+    /// Always log in without opening a browser.
+    #[serde(default)]
+    pub headless: bool,
+
+    /// How this profile reaches Spotify, see `Network`.
+    #[serde(default)]
+    pub network: Network,
+}
+
+#[derive(Clone)]
+pub struct Profile {
+    pub name: String,
+    pub preferences: Preferences,
+}
+
+/// All the profiles that have been added, and the one to use when none is given.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Profiles {
+    active: Option<String>,
+    profiles: BTreeMap<String, Preferences>,
+}
+
+fn music_dir() -> Result<PathBuf, Error> {
+    let home = dirs::home_dir()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "could not find home dir"))?;
+    Ok(home.join("Music/spotify-dl"))
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Profile {
+    /// Holds this profile's credentials, separate from every other profile's.
+    pub fn store(&self) -> Result<CredentialStore, Error> {
+        let dir = store::config_dir()?;
+        if self.name == DEFAULT_PROFILE {
+            CredentialStore::open(dir)
+        } else {
+            CredentialStore::open(dir.join(PROFILES_DIR).join(&self.name))
+        }
+    }
+}
+
+impl Profiles {
+    pub fn load() -> Result<Self, Error> {
+        let path = store::config_dir()?.join(PROFILES_FILE);
+        match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data)
+                .map_err(|err| Error::Profile(format!("{} is corrupted: {}", PROFILES_FILE, err))),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let data = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        fs::write(store::config_dir()?.join(PROFILES_FILE), data)?;
+        Ok(())
+    }
+
+    /// Names of all profiles, including the default one.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = vec![DEFAULT_PROFILE];
+        names.extend(self.profiles.keys().map(String::as_str).filter(|name| *name != DEFAULT_PROFILE));
+        names
+    }
+
+    pub fn active(&self) -> &str {
+        self.active.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Profile, Error> {
+        let preferences = match self.profiles.get(name) {
+            Some(preferences) => preferences.clone(),
+            None if name == DEFAULT_PROFILE => Preferences { library: music_dir()?, headless: false, network: Network::default() },
+            None => return Err(Error::Profile(format!("there is no profile called '{}', add it with 'profile add {}'", name, name))),
+        };
+
+        Ok(Profile { name: name.to_owned(), preferences })
+    }
+
+    /// Adds a profile, downloading to `library` or a folder named after it next to the default
+    /// library.
+    pub fn add(&mut self, name: &str, library: Option<PathBuf>, headless: bool) -> Result<(), Error> {
+        if !valid_name(name) {
+            return Err(Error::Profile("profile names can only contain letters, numbers, '-' and '_'".to_owned()));
+        }
+        if self.names().contains(&name) {
+            return Err(Error::Profile(format!("the profile '{}' already exists", name)));
+        }
+
+        let library = match library {
+            Some(library) => library,
+            None => music_dir()?.join(name),
+        };
+
+        self.profiles.insert(name.to_owned(), Preferences { library, headless, network: Network::default() });
+        self.save()
+    }
+
+    /// Removes a profile and wipes its credentials, the downloaded tracks are kept.
+    pub fn remove(&mut self, name: &str) -> Result<(), Error> {
+        if name == DEFAULT_PROFILE {
+            return Err(Error::Profile("the default profile can not be removed".to_owned()));
+        }
+
+        let profile = self.get(name)?;
+        profile.store()?.wipe()?;
+        let _ = fs::remove_dir(store::config_dir()?.join(PROFILES_DIR).join(name));
+
+        self.profiles.remove(name);
+        if self.active.as_deref() == Some(name) {
+            self.active = None;
+        }
+        self.save()
+    }
+
+    /// Makes `name` the profile used when none is given on the command line.
+    pub fn set_active(&mut self, name: &str) -> Result<(), Error> {
+        self.get(name)?;
+        self.active = Some(name.to_owned());
+        self.save()
+    }
+}
@@ -0,0 +1,578 @@
+use crate::Error;
+use crate::app::{App, MAX_RECONNECTS};
+use crate::availability::{self, Availability};
+use crate::download;
+use crate::error::{Context, Step};
+use crate::spotify;
+
+use crate::spotify::SpotifyId;
+
+use librespot::metadata::Track;
+
+use rustyline::ExternalPrinter;
+use sanitise_file_name::sanitise;
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+/// Where a job is at.
+#[derive(Clone, PartialEq)]
+pub enum Status {
+    Waiting,
+    Running,
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+impl Status {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, Self::Waiting | Self::Running)
+    }
+}
+
+/// How far a running job has come.
+#[derive(Clone, Default)]
+pub struct Progress {
+    pub tracks: usize,
+
+    /// The track being downloaded, counting from 1.
+    pub track: usize,
+    pub track_name: String,
+    pub fetched: usize,
+    pub total: usize,
+
+    pub done: usize,
+    pub failed: usize,
+    pub skipped: usize,
+
+    /// Bytes fetched for the tracks that are done, not counting the current one.
+    pub bytes: u64,
+}
+
+/// What a job downloads.
+#[derive(Clone)]
+pub enum Target {
+    /// Everything a link points to, looked up when the job starts.
+    Link(SpotifyId),
+
+    /// Tracks that were picked when the job was queued, like the ones of a radio station.
+    Tracks { name: String, ids: Vec<SpotifyId> },
+}
+
+impl Target {
+    fn name(&self) -> String {
+        match self {
+            Self::Link(id) => id.to_base62().unwrap_or_default(),
+            Self::Tracks { name, .. } => name.clone(),
+        }
+    }
+}
+
+/// A link waiting to be, or being, downloaded.
+#[derive(Clone)]
+pub struct Job {
+    pub id: usize,
+    pub target: Target,
+    pub name: String,
+    pub export: Option<PathBuf>,
+    pub status: Status,
+    pub progress: Progress,
+    cancel: bool,
+}
+
+#[derive(Default)]
+struct State {
+    jobs: Vec<Job>,
+    next_id: usize,
+    paused: bool,
+
+    /// Whether a track is being written right now.
+    in_track: bool,
+
+    /// The latest printed messages, for views that take over the terminal.
+    log: VecDeque<String>,
+
+    /// Only keep messages in `log` instead of printing them.
+    muted: bool,
+}
+
+const LOG_SIZE: usize = 200;
+
+pub type Printer = Box<dyn ExternalPrinter + Send>;
+
+/// The downloads of this session, worked through one at a time by a background task while the
+/// prompt stays usable.
+#[derive(Clone)]
+pub struct Queue {
+    state: Arc<Mutex<State>>,
+    changed: Arc<Notify>,
+    printer: Arc<Mutex<Option<Printer>>>,
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State { next_id: 1, ..Default::default() })),
+            changed: Arc::new(Notify::new()),
+            printer: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn change<T>(&self, f: impl FnOnce(&mut State) -> T) -> T {
+        let result = f(&mut self.state.lock().unwrap());
+        self.changed.notify_waiters();
+        result
+    }
+
+    /// Waits until `ready` returns something, checking again after every change.
+    async fn wait_for<T>(&self, mut ready: impl FnMut(&mut State) -> Option<T>) -> T {
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            if let Some(result) = ready(&mut self.state.lock().unwrap()) {
+                return result;
+            }
+
+            changed.await;
+        }
+    }
+
+    /// Prints above the prompt instead of through it, once the prompt has handed over a printer.
+    pub fn print(&self, message: String) {
+        let muted = self.change(|state| {
+            if state.log.len() == LOG_SIZE {
+                state.log.pop_front();
+            }
+            state.log.push_back(console::strip_ansi_codes(&message).into_owned());
+            state.muted
+        });
+
+        if muted {
+            return;
+        }
+
+        match *self.printer.lock().unwrap() {
+            Some(ref mut printer) => {
+                if printer.print(format!("{}\n", message)).is_err() {
+                    println!("{}", message);
+                }
+            },
+            None => println!("{}", message),
+        }
+    }
+
+    pub fn set_printer(&self, printer: Printer) {
+        *self.printer.lock().unwrap() = Some(printer);
+    }
+
+    /// While muted, messages are only kept for `log`.
+    pub fn set_muted(&self, muted: bool) {
+        self.change(|state| state.muted = muted);
+    }
+
+    /// The latest `count` messages, oldest first.
+    pub fn log(&self, count: usize) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.log.iter().skip(state.log.len().saturating_sub(count)).cloned().collect()
+    }
+
+    pub fn push(&self, target: Target, export: Option<PathBuf>) -> usize {
+        self.change(|state| {
+            let id = state.next_id;
+            state.next_id += 1;
+            state.jobs.push(Job {
+                id,
+                name: target.name(),
+                target,
+                export,
+                status: Status::Waiting,
+                progress: Progress::default(),
+                cancel: false,
+            });
+            id
+        })
+    }
+
+    pub fn jobs(&self) -> Vec<Job> {
+        self.state.lock().unwrap().jobs.clone()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    /// Whether nothing is running or waiting to run.
+    pub fn is_idle(&self) -> bool {
+        self.state.lock().unwrap().jobs.iter().all(|job| job.status.is_finished())
+    }
+
+    /// Stops the queue once the current track is written.
+    pub fn pause(&self) {
+        self.change(|state| state.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.change(|state| state.paused = false);
+    }
+
+    /// Cancels a job, a running one stops after its current track. Returns whether it was
+    /// running.
+    pub fn cancel(&self, id: usize) -> Result<bool, Error> {
+        self.change(|state| {
+            let job = state.jobs.iter_mut().find(|job| job.id == id)
+                .ok_or_else(|| Error::Queue(format!("there is no job #{}", id)))?;
+
+            match job.status {
+                Status::Waiting => {
+                    job.status = Status::Cancelled;
+                    Ok(false)
+                },
+                Status::Running => {
+                    job.cancel = true;
+                    Ok(true)
+                },
+                _ => Err(Error::Queue(format!("job #{} has already finished", id))),
+            }
+        })
+    }
+
+    /// Drops every job that is waiting or finished, returns how many were dropped.
+    pub fn clear(&self) -> usize {
+        self.change(|state| {
+            let before = state.jobs.len();
+            state.jobs.retain(|job| job.status == Status::Running);
+            before - state.jobs.len()
+        })
+    }
+
+    /// Waits until everything in the queue is finished.
+    pub async fn wait_idle(&self) {
+        self.wait_for(|state| state.jobs.iter().all(|job| job.status.is_finished()).then_some(())).await
+    }
+
+    /// Waits until no track is being written, so quitting doesn't leave half a file behind.
+    pub async fn wait_track_done(&self) {
+        self.wait_for(|state| (!state.in_track).then_some(())).await
+    }
+
+    async fn next_job(&self) -> Job {
+        self.wait_for(|state| {
+            if state.paused {
+                return None;
+            }
+
+            let job = state.jobs.iter_mut().find(|job| job.status == Status::Waiting)?;
+            job.status = Status::Running;
+            Some(job.clone())
+        }).await
+    }
+
+    /// Called between tracks: waits while the queue is paused and stops the job if it was
+    /// cancelled.
+    async fn checkpoint(&self, id: usize) -> Result<(), Error> {
+        self.wait_for(|state| {
+            let cancelled = state.jobs.iter().any(|job| job.id == id && job.cancel);
+            match (cancelled, state.paused) {
+                (true, _) => Some(Err(Error::Cancelled)),
+                (false, true) => None,
+                (false, false) => Some(Ok(())),
+            }
+        }).await
+    }
+
+    fn update(&self, id: usize, f: impl FnOnce(&mut Job)) {
+        self.change(|state| {
+            if let Some(job) = state.jobs.iter_mut().find(|job| job.id == id) {
+                f(job);
+            }
+        })
+    }
+
+    fn set_in_track(&self, in_track: bool) {
+        self.change(|state| state.in_track = in_track);
+    }
+
+    fn finish(&self, id: usize, result: Result<(), Error>) {
+        let mut name = String::new();
+        self.update(id, |job| {
+            name = job.name.clone();
+            job.status = match result {
+                Ok(()) => Status::Done,
+                Err(Error::Cancelled) => Status::Cancelled,
+                Err(ref err) => Status::Failed(err.to_string()),
+            };
+        });
+
+        match result {
+            Ok(()) => self.print(format!("#{} {} finished", id, name)),
+            Err(Error::Cancelled) => self.print(format!("#{} {} cancelled", id, name)),
+            Err(err) => self.print(format!("#{} {} failed: {}", id, name, err)),
+        }
+    }
+}
+
+/// Works through the queue in the background for as long as the program runs.
+pub fn spawn_worker(app: App) {
+    tokio::spawn(async move {
+        loop {
+            let job = app.queue().next_job().await;
+            let result = download_job(&app, &job).await;
+            app.queue().finish(job.id, result);
+        }
+    });
+}
+
+async fn download_job(app: &App, job: &Job) -> Result<(), Error> {
+    let queue = app.queue();
+
+    let checkmark = console::style("✔".to_string()).for_stdout().green();
+    let error     = console::style("✘".to_string()).for_stdout().red();
+
+    if let Some(ref p) = job.export {
+        std::fs::create_dir_all(p)?;
+    }
+
+    if app.session().is_invalid() {
+        app.reconnect().await?;
+    }
+    let session = app.session();
+
+    let (name, tracks) = match job.target {
+        Target::Link(id) => spotify::get_tracks_to_download(id, &session).await?,
+        Target::Tracks { ref name, ref ids } => (name.clone(), ids.clone()),
+    };
+    queue.print(format!("#{} found {} with {} tracks", job.id, name, tracks.len()));
+    queue.update(job.id, |job| {
+        job.name = name;
+        job.progress.tracks = tracks.len();
+    });
+
+    let metadata = spotify::get_tracks_metadata(&tracks, &session).await;
+
+    // work out what can't be downloaded before starting, so it isn't attempted
+    let mut checked = Vec::with_capacity(metadata.len());
+    for result in metadata {
+        match result {
+            Ok(track) => {
+                let availability = availability::check(&track, &session).await;
+                checked.push(Ok((track, availability)));
+            },
+            Err(err) => checked.push(Err(err)),
+        }
+    }
+
+    let skipped = checked.iter().filter(|c| !matches!(c, Ok((_, a)) if a.is_downloadable())).count();
+    let alternatives = checked.iter().filter(|c| matches!(c, Ok((_, Availability::Alternative(_))))).count();
+    if skipped > 0 {
+        queue.print(format!("#{} {} tracks are not available in {} and will be skipped", job.id, skipped, session.country()));
+    }
+    if alternatives > 0 {
+        queue.print(format!("#{} {} tracks will be downloaded from an alternative release", job.id, alternatives));
+    }
+
+    let size = tracks.len();
+	for (mut i, (track_id, checked)) in tracks.into_iter().zip(checked).enumerate() {
+    	i += 1;
+    	queue.checkpoint(job.id).await?;
+
+    	let base62 = track_id.to_base62()?;
+    	let path = Path::new(&base62).with_extension("ogg");
+
+    	let (track, availability) = match checked {
+        	Ok(checked) => checked,
+        	Err(message) => {
+            	queue.print(format!("{} ({:02}/{:02}) {} : {}", error, i, size, base62, message));
+            	queue.update(job.id, |job| job.progress.failed += 1);
+            	continue;
+        	},
+    	};
+
+    	queue.update(job.id, |job| {
+        	job.progress.track = i;
+        	job.progress.track_name = track.name.clone();
+        	job.progress.fetched = 0;
+        	job.progress.total = 0;
+    	});
+
+    	let downloaded = if path.exists() {
+        	queue.print(format!("{} ({:02}/{:02}) {} : exists", checkmark, i, size, base62));
+        	queue.update(job.id, |job| job.progress.done += 1);
+        	true
+    	}
+
+    	else {
+        	let source = match availability {
+            	Availability::Available => &track,
+            	Availability::Alternative(ref alternative) => alternative.as_ref(),
+            	unavailable => {
+                	queue.print(format!("{} ({:02}/{:02}) {} : {}, skipped", error, i, size, base62, unavailable.describe()));
+                	queue.update(job.id, |job| job.progress.skipped += 1);
+                	continue;
+            	},
+        	};
+
+        	// a dropped connection invalidates the session, so replace it and retry this track
+        	queue.set_in_track(true);
+        	let res = with_reconnects(
+            	|| async {
+                	download::download_track(source, &app.session(), &path, |fetched, total| {
+                    	queue.update(job.id, |job| {
+                        	job.progress.fetched = fetched;
+                        	job.progress.total = total;
+                    	});
+                	}).await
+            	},
+            	|| async {
+                	if !app.session().is_invalid() {
+                    	return Ok(false);
+                	}
+                	app.reconnect().await.map(|_| true)
+            	},
+        	).await;
+        	queue.set_in_track(false);
+        	let res = res?;
+
+    		match res {
+        		Ok(name) => {
+                	queue.print(format!("{} ({:02}/{:02}) {} : {}", checkmark, i, size, base62, name));
+                	queue.update(job.id, |job| {
+                    	job.progress.done += 1;
+                    	job.progress.bytes += job.progress.total as u64;
+                    	job.progress.fetched = 0;
+                    	job.progress.total = 0;
+                	});
+                	true
+        		},
+
+        		Err(message) => {
+                	queue.print(format!("{} ({:02}/{:02}) {} : {}", error, i, size, base62, message));
+                	queue.update(job.id, |job| job.progress.failed += 1);
+                	false
+        		},
+    		}
+    	};
+
+    	// there is nothing to export when the download failed
+    	if !downloaded {
+        	continue;
+    	}
+
+    	if let Some(ref p) = job.export {
+        	if let Err(message) = export(queue, &track, &path, p) {
+            	queue.print(message.to_string());
+        	}
+    	}
+	}
+
+	Ok(())
+}
+
+/// Runs `attempt` until it succeeds or `reconnect` returns `false`, at most `MAX_RECONNECTS`
+/// more times. `reconnect` replaces the session when a dropped connection invalidated it, and
+/// returns whether it did, an error means reconnecting failed.
+async fn with_reconnects<T, A, AF, R, RF>(mut attempt: A, mut reconnect: R) -> Result<Result<T, Error>, Error>
+where
+    A: FnMut() -> AF,
+    AF: Future<Output = Result<T, Error>>,
+    R: FnMut() -> RF,
+    RF: Future<Output = Result<bool, Error>>,
+{
+    let mut reconnects = 0;
+    loop {
+        let res = attempt().await;
+        if res.is_err() && reconnects < MAX_RECONNECTS && reconnect().await? {
+            reconnects += 1;
+            continue;
+        }
+
+        return Ok(res);
+    }
+}
+
+fn export(queue: &Queue, track: &Track, path: &Path, export_path: &Path) -> Result<(), Error> {
+    let dest = export_path.join(sanitise(&track.name)).with_extension("ogg");
+
+    queue.print(format!("exporting {}", dest.display()));
+    std::fs::copy(path, dest).during(track.id, Step::Export)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::record::test::{recorder, TempDir};
+    use crate::record::Recorder;
+
+    use std::io::{self, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn retried_tracks_are_fetched_again() {
+        let dir = TempDir::new();
+        let path = dir.0.join("track.ogg");
+        let attempts = AtomicUsize::new(0);
+
+        // like `download_track`: refuses existing files, and the first attempt loses the connection
+        let res = with_reconnects(
+            || async {
+                let attempt = attempts.fetch_add(1, Ordering::Relaxed) + 1;
+                if path.exists() {
+                    return Err(Error::Queue("exists".to_string()));
+                }
+
+                let mut recorder = recorder(&path, 0);
+                recorder.write_all(b"OggS")?;
+                if attempt == 1 {
+                    return Err(Error::Io(io::ErrorKind::ConnectionReset.into()));
+                }
+                recorder.finish()?;
+                Ok(attempt)
+            },
+            || async { Ok(true) },
+        ).await;
+
+        assert_eq!(res.unwrap().unwrap(), 2);
+        assert_eq!(std::fs::read(&path).unwrap(), b"OggS");
+        assert!(!Recorder::part_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn failures_with_a_valid_session_are_not_retried() {
+        let attempts = AtomicUsize::new(0);
+
+        let res: Result<Result<(), Error>, Error> = with_reconnects(
+            || async {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                Err(Error::Queue("failed".to_string()))
+            },
+            || async { Ok(false) },
+        ).await;
+
+        assert!(res.unwrap().is_err());
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn reconnects_are_limited() {
+        let attempts = AtomicUsize::new(0);
+
+        let res: Result<Result<(), Error>, Error> = with_reconnects(
+            || async {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                Err(Error::Queue("failed".to_string()))
+            },
+            || async { Ok(true) },
+        ).await;
+
+        assert!(res.unwrap().is_err());
+        assert_eq!(attempts.load(Ordering::Relaxed), MAX_RECONNECTS as usize + 1);
+    }
+}
@@ -1,30 +1,30 @@
-use librespot::playback::audio_backend::{ Sink, SinkError, SinkResult};
-// use librespot::playback::config::AudioFormat;
-use librespot::playback::decoder::AudioPacket;
-use librespot::playback::convert::Converter;
-
-use librespot::metadata::{
-    // Metadata,
-    // Playlist,
-    Track,
-    // Album,
-    // Artist,
-};
-
-use std::path::Path;
+use crate::Error;
+
+use librespot::metadata::Track;
+
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::process::Command;
-use std::io::Write;
+use std::io::{self, Write};
 
-#[allow(dead_code)]
-pub struct RecordSink {
+/// Pipes an ogg/vorbis stream into ffmpeg, which tags it with the track's
+/// metadata and writes it to disk.
+///
+/// The file is written next to its final path and only moved there once ffmpeg is done, so a
+/// download that fails partway never looks finished. Dropping an unfinished recorder stops
+/// ffmpeg and removes what it wrote.
+pub struct Recorder {
     process: std::process::Child,
-    stream:  std::process::ChildStdin,
+    stream:  Option<std::process::ChildStdin>,
+    path:    PathBuf,
+    part:    PathBuf,
+    done:    bool,
 }
 
-impl RecordSink {
-    fn get_artists_string(track: &Track) -> String {
-		let mut artists = String::new();
+impl Recorder {
+    pub fn get_artists_string(track: &Track) -> String {
+        let mut artists = String::new();
         let mut i = 0;
         while i < track.artists.len() {
             artists.push_str(track.artists[i].name.as_str());
@@ -34,50 +34,164 @@ impl RecordSink {
             }
         }
 
-        return artists
+        artists
+    }
+
+    /// Where the file for `path` is written until it is complete.
+    pub fn part_path(path: &Path) -> PathBuf {
+        let mut part = path.as_os_str().to_owned();
+        part.push(".part");
+        PathBuf::from(part)
     }
 
-    pub fn create(path: &Path, track: Track) -> Box<dyn Sink> {
-        // let path = format!("{}.ogg", track.id.to_base62().unwrap());
-        let artists = Self::get_artists_string(&track);
+    pub fn create(path: &Path, track: &Track) -> Result<Self, Error> {
+        let artists = Self::get_artists_string(track);
 
-        let mut process = Command::new("ffmpeg")
+        let mut command = Command::new("ffmpeg");
+        command
+            .arg("-y")
             .arg("-f").arg("ogg")
             .arg("-i").arg("pipe:")
-            .arg("-metadata").arg(&format!("title={}", track.name))
-            .arg("-metadata").arg(&format!("album={}", track.album.name))
-            .arg("-metadata").arg(&format!("artist={}", artists))
-            .arg(path)
+            .arg("-c").arg("copy")
+            .arg("-metadata").arg(format!("title={}", track.name))
+            .arg("-metadata").arg(format!("album={}", track.album.name))
+            .arg("-metadata").arg(format!("artist={}", artists))
+            .arg("-f").arg("ogg");
+
+        Self::spawn(command, path)
+    }
+
+    // Starts `command` with the path of the part file as its last argument
+    fn spawn(mut command: Command, path: &Path) -> Result<Self, Error> {
+        let part = Self::part_path(path);
+
+        let mut process = command
+            .arg(&part)
             .stdin(Stdio::piped())
             .stdout(Stdio::null())
             .stderr(Stdio::null())
             .spawn()
-            .expect("failed to open ffmpeg");
+            .map_err(|err| Error::Ffmpeg(format!("could not start ffmpeg: {}", err)))?;
 
-        let stream = process.stdin.take().unwrap();
-        let output = Self { process, stream };
-        Box::new(output)
+        let stream = process.stdin.take();
+        Ok(Self { process, stream, path: path.to_owned(), part, done: false })
     }
-}
 
-impl Sink for RecordSink {
-    fn start(&mut self) -> SinkResult<()> {
+    /// Closes ffmpeg's input, waits for it to finish writing the file and moves the file to
+    /// its final path.
+    pub fn finish(mut self) -> Result<(), Error> {
+        drop(self.stream.take());
+
+        let status = self.process.wait()?;
+        if !status.success() {
+            return Err(Error::Ffmpeg(format!("exited with {}", status)));
+        }
+
+        fs::rename(&self.part, &self.path)?;
+        self.done = true;
+
         Ok(())
     }
+}
 
-    fn stop(&mut self) -> SinkResult<()> {
-        Ok(())
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        drop(self.stream.take());
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = fs::remove_file(&self.part);
     }
+}
 
-    fn write(&mut self, packet: AudioPacket, _conveter: &mut Converter) -> SinkResult<()> {
-        let AudioPacket::Raw(bytes) = packet else {
-            panic!("found non-raw samples");
-        };
+impl Write for Recorder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stream {
+            Some(ref mut stream) => stream.write(buf),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
 
-        if self.stream.write(&bytes).is_err() {
-            return Err(SinkError::OnWrite("failed to write bytes to ffmpeg".to_owned()));
-        };
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stream {
+            Some(ref mut stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A directory of its own for each test, removed when dropped.
+    pub struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub fn new() -> Self {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let name = format!("spotify-dl-test-{}-{}", std::process::id(), COUNT.fetch_add(1, Ordering::Relaxed));
+            let path = std::env::temp_dir().join(name);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A recorder that writes its input as-is through a shell, exiting with `status`.
+    pub fn recorder(path: &Path, status: i32) -> Recorder {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!("cat > \"$0\"; exit {}", status));
+        Recorder::spawn(command, path).unwrap()
+    }
+
+    #[test]
+    fn finished_files_are_moved_in_place() {
+        let dir = TempDir::new();
+        let path = dir.0.join("track.ogg");
+
+        let mut recorder = recorder(&path, 0);
+        recorder.write_all(b"OggS").unwrap();
+        recorder.finish().unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"OggS");
+        assert!(!Recorder::part_path(&path).exists());
+    }
+
+    #[test]
+    fn failed_downloads_leave_no_file_behind() {
+        let dir = TempDir::new();
+        let path = dir.0.join("track.ogg");
+
+        // the copy failing partway drops the recorder
+        let mut recorder = recorder(&path, 0);
+        recorder.write_all(b"Ogg").unwrap();
+        drop(recorder);
+
+        assert!(!path.exists());
+        assert!(!Recorder::part_path(&path).exists());
+    }
+
+    #[test]
+    fn failed_ffmpeg_leaves_no_file_behind() {
+        let dir = TempDir::new();
+        let path = dir.0.join("track.ogg");
+
+        let mut recorder = recorder(&path, 1);
+        recorder.write_all(b"OggS").unwrap();
+        assert!(recorder.finish().is_err());
+
+        assert!(!path.exists());
+        assert!(!Recorder::part_path(&path).exists());
     }
 }
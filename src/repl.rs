@@ -0,0 +1,160 @@
+use crate::profile::Profiles;
+use crate::store;
+
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Config, Context, Editor, Helper};
+
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+const HISTORY_FILE: &str = "history.txt";
+const HISTORY_SIZE: usize = 1000;
+
+/// Every command, its aliases and the arguments it takes.
+const COMMANDS: &[(&str, &[&str], &str)] = &[
+    ("download", &["d"], "[--dry-run] <link>"),
+    ("export",   &["e"], "<path> <link>"),
+    ("list",     &["l"], "<link>"),
+    ("search",   &["s"], "<query>"),
+    ("radio",    &["r"], "<link> [--count N]"),
+    ("profile",  &["profiles"], "list | add <name> [--headless] [library] | remove <name> | use <name>"),
+    ("dashboard", &["watch"], ""),
+    ("whoami",   &[], ""),
+    ("logout",   &[], ""),
+    ("help",     &["h", "?"], ""),
+    ("quit",     &["q", "exit"], ""),
+];
+
+const PROFILE_COMMANDS: &[&str] = &["list", "add", "remove", "use"];
+
+fn find_command(word: &str) -> Option<&'static (&'static str, &'static [&'static str], &'static str)> {
+    COMMANDS.iter().find(|(name, aliases, _)| *name == word || aliases.contains(&word))
+}
+
+fn pairs<'a>(words: impl IntoIterator<Item = &'a str>, prefix: &str) -> Vec<Pair> {
+    words.into_iter()
+        .filter(|word| word.starts_with(prefix))
+        .map(|word| Pair { display: word.to_owned(), replacement: format!("{} ", word) })
+        .collect()
+}
+
+/// Completes commands, their flags, paths for `export` and links that were used before, and
+/// hints at the arguments of the command being typed.
+pub struct ReplHelper {
+    files: FilenameCompleter,
+    links: Vec<String>,
+}
+
+impl ReplHelper {
+    /// Makes the links in `line` available for completion.
+    pub fn remember(&mut self, line: &str) {
+        for word in line.split_whitespace().filter(|word| word.contains("open.spotify.com")) {
+            self.links.retain(|link| link != word);
+            self.links.push(word.to_owned());
+        }
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..];
+
+        // the words before the one being completed
+        let done: Vec<&str> = line[..start].split_whitespace().collect();
+
+        let candidates = match done.as_slice() {
+            [] => pairs(COMMANDS.iter().map(|(name, _, _)| *name), word),
+
+            [command] if matches!(*command, "e" | "export") => return self.files.complete_path(line, pos),
+            [command] if matches!(*command, "d" | "download") && word.starts_with('-') => pairs(["--dry-run"], word),
+            [command, ..] if matches!(*command, "r" | "radio") && word.starts_with('-') => pairs(["--count"], word),
+
+            [command] if matches!(*command, "profile" | "profiles") => pairs(PROFILE_COMMANDS.iter().copied(), word),
+            [command, "use" | "remove"] if matches!(*command, "profile" | "profiles") => {
+                let profiles = Profiles::load().unwrap_or_default();
+                pairs(profiles.names(), word)
+            },
+            [command, "add", _, ..] if matches!(*command, "profile" | "profiles") => pairs(["--headless"], word),
+
+            [command, ..] if matches!(*command, "s" | "search" | "profile" | "profiles") => Vec::new(),
+            _ => pairs(self.links.iter().rev().map(String::as_str), word),
+        };
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() || line.is_empty() {
+            return None;
+        }
+
+        let mut words = line.split_whitespace();
+        let first = words.next()?;
+        if words.next().is_some() {
+            return None;
+        }
+
+        if line.ends_with(char::is_whitespace) {
+            let (_, _, usage) = find_command(first)?;
+            return (!usage.is_empty()).then(|| usage.to_string());
+        }
+
+        let mut matching = COMMANDS.iter().filter(|(name, _, _)| name.starts_with(first));
+        let (name, _, usage) = matching.next()?;
+        if matching.next().is_some() {
+            return None;
+        }
+
+        let rest = &name[first.len()..];
+        Some(if usage.is_empty() { rest.to_owned() } else { format!("{} {}", rest, usage) })
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(console::style(hint).dim().to_string())
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Where the REPL's history is kept between runs.
+pub fn history_path() -> Option<PathBuf> {
+    store::config_dir().ok().map(|dir| dir.join(HISTORY_FILE))
+}
+
+/// Creates the line editor, with the history of earlier runs loaded.
+pub fn editor() -> rustyline::Result<Editor<ReplHelper, FileHistory>> {
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .max_history_size(HISTORY_SIZE)?
+        .auto_add_history(false)
+        .build();
+
+    let mut rl = Editor::with_config(config)?;
+    if let Some(path) = history_path() {
+        let _ = rl.load_history(&path);
+    }
+
+    let mut helper = ReplHelper { files: FilenameCompleter::new(), links: Vec::new() };
+    for line in rl.history().iter() {
+        helper.remember(line);
+    }
+
+    rl.set_helper(Some(helper));
+    Ok(rl)
+}
@@ -0,0 +1,81 @@
+use crate::Error;
+
+use crate::spotify::Session;
+use crate::spotify::SpotifyId;
+
+use librespot::metadata::search::{AlbumHit, ArtistHit, PlaylistHit, TrackHit};
+use librespot::metadata::{SearchResults, SearchType};
+
+use dialoguer::MultiSelect;
+
+const SEARCH_LIMIT: usize = 10;
+
+/// The kinds of results that can be downloaded.
+const SEARCH_TYPES: [SearchType; 4] = [SearchType::Track, SearchType::Album, SearchType::Artist, SearchType::Playlist];
+
+/// One thing the search found, with the line it is shown as.
+pub struct SearchResult {
+    pub id: SpotifyId,
+    pub label: String,
+}
+
+impl SearchResult {
+    fn new(kind: &str, id: SpotifyId, name: &str, by: &str) -> Self {
+        let label = if by.is_empty() {
+            format!("{:<8} {}", kind, name)
+        } else {
+            format!("{:<8} {} - {}", kind, name, by)
+        };
+
+        Self { id, label }
+    }
+}
+
+impl From<&TrackHit> for SearchResult {
+    fn from(hit: &TrackHit) -> Self {
+        Self::new("track", hit.id, &hit.name, &hit.artists.join(", "))
+    }
+}
+
+impl From<&AlbumHit> for SearchResult {
+    fn from(hit: &AlbumHit) -> Self {
+        Self::new("album", hit.id, &hit.name, &hit.artists.join(", "))
+    }
+}
+
+impl From<&ArtistHit> for SearchResult {
+    fn from(hit: &ArtistHit) -> Self {
+        Self::new("artist", hit.id, &hit.name, "")
+    }
+}
+
+impl From<&PlaylistHit> for SearchResult {
+    fn from(hit: &PlaylistHit) -> Self {
+        Self::new("playlist", hit.id, &hit.name, hit.author.as_deref().unwrap_or_default())
+    }
+}
+
+/// Searches the catalogue for tracks, albums, artists and playlists matching `query`.
+pub async fn search(query: &str, session: &Session) -> Result<Vec<SearchResult>, Error> {
+    let results = SearchResults::get(session, query, &SEARCH_TYPES, SEARCH_LIMIT, 0).await?;
+    let tracks = results.tracks.hits.iter().map(SearchResult::from);
+    let albums = results.albums.hits.iter().map(SearchResult::from);
+    let artists = results.artists.hits.iter().map(SearchResult::from);
+    let playlists = results.playlists.hits.iter().map(SearchResult::from);
+    Ok(tracks.chain(albums).chain(artists).chain(playlists).collect())
+}
+
+/// Shows `results` as a numbered list and returns the ones the user picked.
+pub fn choose(results: &[SearchResult]) -> Result<Vec<SpotifyId>, Error> {
+    let items: Vec<String> = results.iter().enumerate()
+        .map(|(i, result)| format!("{:>2}. {}", i + 1, result.label))
+        .collect();
+
+    let selected = MultiSelect::new()
+        .with_prompt("pick with space, download with enter, cancel with esc")
+        .items(&items)
+        .interact_opt()
+        .map_err(|err| Error::Io(std::io::Error::other(err)))?;
+
+    Ok(selected.unwrap_or_default().into_iter().map(|i| results[i].id).collect())
+}
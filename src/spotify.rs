@@ -1,17 +1,11 @@
 use crate::Error;
+use crate::error::{Context, Step};
 
 pub use librespot::core::authentication::Credentials;
 pub use librespot::core::config::SessionConfig;
 pub use librespot::core::session::Session;
 pub use librespot::core::spotify_id::SpotifyId;
 
-use librespot::oauth;
-
-use librespot::playback::config as playback_config;
-use librespot::playback::player;
-use librespot::playback::player::PlayerEvent;
-use librespot::playback::mixer;
-
 use librespot::core::spotify_id::SpotifyItemType;
 
 use librespot::metadata::{
@@ -19,18 +13,15 @@ use librespot::metadata::{
     Playlist,
     Track,
     Album,
+    Artist,
+    Station,
+    MetadataCache,
 };
 
-use futures_executor::block_on;
-
+use futures_util::TryStreamExt;
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use std::io::Write;
-use std::env;
-use std::path::Path;
-
-use crate::RecordSink;
 
 pub fn parse_link(input: &str) -> Result<SpotifyId, Error> {
     lazy_static! {
@@ -45,149 +36,174 @@ pub fn parse_link(input: &str) -> Result<SpotifyId, Error> {
 
     let uri = format!("spotify:{}:{}", audio_type, id);
 
-    return SpotifyId::from_uri(&uri).or(Err(Error::invalid_link(input)))
-}
-
-fn get_stored_credentials() -> Result<Credentials, ()> {
-    let token_cache = "access_token.txt";
-
-    let token = std::fs::read_to_string(token_cache).or(Err(()))?;
-    env::set_var("SPOTIFY_DL_ACCESS_TOKEN", &token);
-    let creds = Credentials::with_access_token(token);
-
-    Ok(creds)
-}
-
-fn try_oauth_login() -> Result<Credentials, ()> {
-    let client_id = "c85b2435db4948bab5fcd3386b77170c";
-    let callback_url = "http://localhost:8888/callback";
-
-    let mut privelages = Vec::new();
-    privelages.push("playlist-read-private");
-    privelages.push("streaming");
-
-    let oauth_client = oauth::OAuthClientBuilder::new(client_id, callback_url, privelages)
-    	.open_in_browser()
-    	.with_custom_message("go back to your terminal :)")
-		.build()
-		.unwrap();
-
-    let oauth_token = oauth_client.get_access_token().unwrap();
-
-    if let Ok(mut out) = std::fs::File::create("access_token.txt") {
-    	println!("saving access token to access_token.txt");
-    	out.write_all(oauth_token.access_token.as_bytes()).or(Err(()))?;
-    } else {
-    	println!("could not save access token");
-    }
-
-    env::set_var("SPOTIFY_DL_ACCESS_TOKEN", &oauth_token.access_token);
-    let creds = Credentials::with_access_token(oauth_token.access_token);
-    Ok(creds)
+    SpotifyId::from_uri(&uri).or(Err(Error::invalid_link(input)))
 }
 
-pub fn connect(session: &Session) -> Result<Credentials, ()> {
-    if let Ok(creds) = get_stored_credentials() {
-        if block_on(session.connect(creds.clone(), true)).is_ok() {
-			return Ok(creds);
-        }
-    }
-
-    if let Ok(creds) = try_oauth_login() {
-        if block_on(session.connect(creds.clone(), true)).is_ok() {
-			return Ok(creds);
-        }
-    }
-
-    return Err(())
-}
-
-pub fn get_tracks_to_download(id: SpotifyId, session: &Session) -> Vec<SpotifyId> {
+/// Resolves a link to the tracks it contains, along with a description of what it points to.
+pub async fn get_tracks_to_download(id: SpotifyId, session: &Session) -> Result<(String, Vec<SpotifyId>), Error> {
     let mut output = Vec::new();
+    let name;
 
 	match id.item_type {
     	SpotifyItemType::Playlist => {
-            let playlist = block_on(Playlist::get(&session, &id)).expect("failed to fetch playlist");
-            let mut count = 0;
-			for track in playlist.tracks() {
-    			output.push(*track);
-    			count += 1;
-			}
-            println!("found playlist: {} with {} songs", playlist.name(), count);
+            let playlist = Playlist::get(session, &id).await?;
+            // large playlists only come with their first items, the rest is fetched page by page
+            let items: Vec<_> = playlist.items(session).try_collect().await?;
+			output.extend(items.into_iter().map(|item| item.id));
+            name = format!("playlist {}", playlist.name());
     	},
 
     	SpotifyItemType::Album => {
-            let album = block_on(Album::get(&session, &id)).expect("failed to fetch album");
-            let mut count = 0;
-			for track in album.tracks() {
-    			output.push(*track);
-    			count += 1;
-			}
-            println!("found album: {} with {} songs", album.name, count);
+            let album = Album::get(session, &id).await?;
+			output.extend(album.tracks().copied());
+            name = format!("album {}", album.name);
     	},
 
     	SpotifyItemType::Track    => {
-            let track = block_on(Track::get(&session, &id)).expect("failed to fetch track");
-            println!("found track: {}", track.name);
+            let track = Track::get(session, &id).await?;
+            name = format!("track {}", track.name);
         	output.push(id);
     	},
 
-    	SpotifyItemType::Episode  => println!("episode"),
-    	SpotifyItemType::Show     => println!("show"),
-    	SpotifyItemType::Local    => println!("local"),
-    	SpotifyItemType::Artist   => println!("artist"),
-    	SpotifyItemType::Unknown  => println!("unknown"),
+    	SpotifyItemType::Artist   => {
+            let artist = Artist::get(session, &id).await?;
+            let top_tracks = artist.top_tracks.for_country(&session.country());
+            output.extend(top_tracks.iter().copied());
+            name = format!("the top tracks of {}", artist.name);
+    	},
+
+    	SpotifyItemType::Episode  => name = "an episode".to_owned(),
+    	SpotifyItemType::Show     => name = "a show".to_owned(),
+    	SpotifyItemType::Local    => name = "a local file".to_owned(),
+    	SpotifyItemType::Unknown  => name = "something unknown".to_owned(),
 	}
 
-	return output;
+	Ok((name, output))
 }
 
-pub async fn record_track(track: SpotifyId, session: Session) -> Result<String, Error> {
-    let mut player_config = playback_config::PlayerConfig::default();
-    player_config.passthrough = true;
+const METADATA_CACHE_SIZE: u64 = 256 * 1024 * 1024;
 
-    let metadata = Track::get(&session, &track).await.or(Err(Error::Unavailable(track)))?;
-    let path = Path::new(&track.to_base62().unwrap()).with_extension("ogg");
+/// Keeps metadata in the user's cache directory between runs, so links that were looked up
+/// before resolve without asking Spotify again. Must be called before the first request.
+pub fn install_metadata_cache() {
+    let Some(dir) = dirs::cache_dir() else {
+        return;
+    };
 
-    if std::path::Path::new(&path).exists() {
-        return Err(Error::Exists(track));
-        // return Err("already exists".into());
+    match MetadataCache::new(dir.join("spotify-dl").join("metadata"), Some(METADATA_CACHE_SIZE)) {
+        Ok(cache) => {
+            let _ = MetadataCache::set(cache);
+        },
+        Err(err) => println!("metadata will not be cached: {}", err),
     }
+}
 
-    let name = metadata.name.clone();
+/// How many tracks a radio station has when no count is given, the same as Spotify's apps.
+pub const RADIO_SIZE: usize = 50;
 
-    let player = player::Player::new(player_config, session, Box::new(mixer::NoOpVolume), move || {
-        RecordSink::create(&path, metadata)
-    });
+/// Generates a radio station for a track, artist, album or playlist, returning its title and
+/// tracks.
+pub async fn get_radio_tracks(seed: SpotifyId, count: usize, session: &Session) -> Result<(String, Vec<SpotifyId>), Error> {
+    match seed.item_type {
+        SpotifyItemType::Track | SpotifyItemType::Artist | SpotifyItemType::Album | SpotifyItemType::Playlist => (),
+        _ => return Err(Error::NoRadio(seed)),
+    }
 
-    player.load(track, true, 0);
+    let station = Station::get(session, &seed, count).await?;
+    let title = match station.title.as_str() {
+        "" => format!("{} radio", get_name(seed, session).await?),
+        title => title.to_owned(),
+    };
 
-    let mut channel = player.get_player_event_channel();
-    while let Some(event) = channel.recv().await {
-        match event {
-            PlayerEvent::Playing      {..} => {},
-            PlayerEvent::TrackChanged {..} => {},
-            PlayerEvent::TimeToPreloadNextTrack {..} => (),
+    Ok((title, station.tracks))
+}
 
-            PlayerEvent::Unavailable  {..} => {
-                return Err(Error::Unavailable(track))
-                // return Err(format!("unavailable, you can try again later - {}", name));
-            },
+// The name of a track, artist, album or playlist
+async fn get_name(id: SpotifyId, session: &Session) -> Result<String, Error> {
+    let name = match id.item_type {
+        SpotifyItemType::Track    => Track::get(session, &id).await?.name,
+        SpotifyItemType::Artist   => Artist::get(session, &id).await?.name,
+        SpotifyItemType::Album    => Album::get(session, &id).await?.name,
+        SpotifyItemType::Playlist => Playlist::get(session, &id).await?.name().to_owned(),
+        _ => return Err(Error::Unavailable(id)),
+    };
+
+    Ok(name)
+}
+
+/// Fetches the metadata of many tracks, batched into as few requests as possible. The results
+/// are in the same order as `ids`.
+pub async fn get_tracks_metadata(ids: &[SpotifyId], session: &Session) -> Vec<Result<Track, Error>> {
+    Track::get_many(session, ids).await
+        .into_iter()
+        .zip(ids)
+        .map(|(result, id)| result.during(*id, Step::Metadata))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use librespot::protocol::metadata::Artist as ArtistMessage;
+    use librespot_core::stand_in::{track_id, Reply, StandIn};
+    use protobuf::Message;
+    use serde_json::json;
+
+    const SEED: &str = "spotify:artist:0gxyHStUsqpMadRV0Di1Qt";
+
+    // Answers for a station called `title` with `tracks` tracks, and for the seed artist
+    async fn radio(title: &'static str, tracks: usize) -> StandIn {
+        StandIn::new(move |request| {
+            if request.target.starts_with("/metadata/4/artist/") {
+                let mut artist = ArtistMessage::new();
+                artist.set_gid(SpotifyId::from_uri(SEED).unwrap().to_raw().to_vec());
+                artist.set_name("Rick Astley".to_owned());
+                return Reply::ok(artist.write_to_bytes().unwrap());
+            }
+
+            let tracks: Vec<_> = (0..tracks)
+                .map(|n| json!({ "uri": track_id(n).to_uri().unwrap() }))
+                .collect();
+            Reply::ok(json!({ "title": title, "tracks": tracks }).to_string())
+        }).await
+    }
 
-            PlayerEvent::Paused {..} => {
-                player.stop();
-                return Err(Error::EarlyPause);
-                // return Err(format!("received the pause command, aborting - {}", name));
-            },
+    #[tokio::test]
+    async fn radio_stations_have_up_to_count_tracks() {
+        let stand_in = radio("Rick Astley Radio", 50).await;
+        let session = stand_in.session();
+
+        let seed = SpotifyId::from_uri(SEED).unwrap();
+        let (title, tracks) = get_radio_tracks(seed, 5, &session).await.unwrap();
+        assert_eq!(title, "Rick Astley Radio");
+        assert_eq!(tracks, (0..5).map(track_id).collect::<Vec<_>>());
+        assert_eq!(stand_in.requests()[0].query("count").as_deref(), Some("5"));
+
+        // stations that come up short are taken as they are
+        let short = radio("Rick Astley Radio", 3).await;
+        let session = short.session();
+        let (_, tracks) = get_radio_tracks(seed, RADIO_SIZE, &session).await.unwrap();
+        assert_eq!(tracks.len(), 3);
+    }
 
-            PlayerEvent::EndOfTrack {..} => {
-                player.stop();
-                return Ok(name);
-            },
+    #[tokio::test]
+    async fn radio_stations_without_a_title_are_named_after_the_seed() {
+        let stand_in = radio("", 1).await;
+        let session = stand_in.session();
 
-            event => println!("{:?}", event),
-        }
+        let seed = SpotifyId::from_uri(SEED).unwrap();
+        let (title, _) = get_radio_tracks(seed, RADIO_SIZE, &session).await.unwrap();
+        assert_eq!(title, "Rick Astley radio");
     }
 
-    Ok(format!("{}", name))
+    #[tokio::test]
+    async fn radio_stations_need_a_track_artist_album_or_playlist() {
+        let stand_in = radio("", 1).await;
+        let session = stand_in.session();
+
+        let seed = SpotifyId::from_uri("spotify:show:4rOoJ6Egrf8K2IrywzwOMk").unwrap();
+        assert!(matches!(get_radio_tracks(seed, RADIO_SIZE, &session).await, Err(Error::NoRadio(_))));
+        assert_eq!(stand_in.served(), 0);
+    }
 }
@@ -0,0 +1,354 @@
+use crate::Error;
+
+use crate::spotify::Credentials;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const APP_DIR: &str = "spotify-dl";
+const CREDENTIALS_FILE: &str = "credentials.json";
+const PASSPHRASE_VAR: &str = "SPOTIFY_DL_PASSPHRASE";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+#[cfg(not(test))]
+const KDF_ROUNDS: u32 = 200_000;
+// deriving keys the real way takes seconds without optimisations
+#[cfg(test)]
+const KDF_ROUNDS: u32 = 1_000;
+
+/// Everything needed to log in again without asking the user.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Secrets {
+    pub credentials: Option<Credentials>,
+    pub refresh_token: Option<String>,
+}
+
+/// The file on disk, either holding the secrets as they are or encrypted with a key derived from
+/// a passphrase.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "lowercase")]
+enum Envelope {
+    Plain { secrets: Secrets },
+    Encrypted { salt: String, nonce: String, data: String },
+}
+
+/// Keeps the login secrets in the user's config directory, readable only by the user and
+/// optionally encrypted with a passphrase.
+pub struct CredentialStore {
+    path: PathBuf,
+    passphrase: Option<String>,
+}
+
+/// `$XDG_CONFIG_HOME/spotify-dl` on linux, the equivalent on other platforms.
+pub fn config_dir() -> Result<PathBuf, Error> {
+    let base = dirs::config_dir()
+        .ok_or_else(|| Error::Credentials("could not find the config directory".to_owned()))?;
+    private_dir(base.join(APP_DIR))
+}
+
+/// Creates `dir` if needed, so that only the user can look inside.
+pub fn private_dir(dir: PathBuf) -> Result<PathBuf, Error> {
+    fs::create_dir_all(&dir)?;
+    restrict_permissions(&dir, 0o700)?;
+    Ok(dir)
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path, mode: u32) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path, _mode: u32) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Creates `path` (or truncates it) so that nobody but the owner can read it, even for the short
+/// moment before the contents are written.
+fn create_private(path: &Path) -> Result<File, Error> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let file = options.open(path)?;
+    restrict_permissions(path, 0o600)?;
+    Ok(file)
+}
+
+/// Overwrites the contents of `path` before removing it, so the secrets don't linger on disk.
+pub fn wipe_file(path: &Path) -> Result<(), Error> {
+    let len = match fs::metadata(path) {
+        Ok(metadata) => metadata.len() as usize,
+        Err(_) => return Ok(()),
+    };
+
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0; len])?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, KDF_ROUNDS, &mut key);
+    key
+}
+
+fn decode(field: &str) -> Result<Vec<u8>, Error> {
+    BASE64.decode(field)
+        .map_err(|_| Error::Credentials("the stored credentials are corrupted".to_owned()))
+}
+
+/// Asks for a new passphrase twice, for when encryption is turned on.
+pub fn prompt_new_passphrase() -> Result<String, Error> {
+    let passphrase = rpassword::prompt_password("passphrase for the stored credentials: ")?;
+    if passphrase.is_empty() {
+        return Err(Error::Credentials("the passphrase can not be empty".to_owned()));
+    }
+
+    let repeated = rpassword::prompt_password("repeat the passphrase: ")?;
+    if passphrase != repeated {
+        return Err(Error::Credentials("the passphrases do not match".to_owned()));
+    }
+
+    Ok(passphrase)
+}
+
+impl CredentialStore {
+    /// The store inside `dir`, which is created if needed.
+    pub fn open(dir: PathBuf) -> Result<Self, Error> {
+        Ok(Self {
+            path: private_dir(dir)?.join(CREDENTIALS_FILE),
+            passphrase: None,
+        })
+    }
+
+    /// A store kept somewhere else, e.g. a file copied over from another machine.
+    pub fn at(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            passphrase: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Whether the secrets are, or will be, written encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.passphrase.is_some()
+    }
+
+    /// Encrypts everything saved from now on with `passphrase`.
+    pub fn set_passphrase(&mut self, passphrase: String) {
+        self.passphrase = Some(passphrase);
+    }
+
+    /// Reads the stored secrets, asking for the passphrase if they are encrypted and it isn't set
+    /// in `SPOTIFY_DL_PASSPHRASE`.
+    pub fn load(&mut self) -> Result<Secrets, Error> {
+        let data = match fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(_) => return Ok(Secrets::default()),
+        };
+
+        let envelope: Envelope = serde_json::from_str(&data)
+            .map_err(|_| Error::Credentials("the stored credentials are corrupted".to_owned()))?;
+
+        let (salt, nonce, data) = match envelope {
+            Envelope::Plain { secrets } => return Ok(secrets),
+            Envelope::Encrypted { salt, nonce, data } => (decode(&salt)?, decode(&nonce)?, decode(&data)?),
+        };
+
+        if nonce.len() != NONCE_LEN {
+            return Err(Error::Credentials("the stored credentials are corrupted".to_owned()));
+        }
+
+        let passphrase = match (self.passphrase.take(), std::env::var(PASSPHRASE_VAR)) {
+            (Some(passphrase), _) => passphrase,
+            (None, Ok(passphrase)) => passphrase,
+            (None, Err(_)) => rpassword::prompt_password("passphrase for the stored credentials: ")?,
+        };
+
+        let cipher = Aes256Gcm::new(&derive_key(&passphrase, &salt).into());
+        let plain = cipher.decrypt(Nonce::from_slice(&nonce), data.as_ref())
+            .map_err(|_| Error::Credentials("wrong passphrase for the stored credentials".to_owned()))?;
+
+        let secrets = serde_json::from_slice(&plain)
+            .map_err(|_| Error::Credentials("the stored credentials are corrupted".to_owned()))?;
+
+        self.passphrase = Some(passphrase);
+        Ok(secrets)
+    }
+
+    pub fn save(&self, secrets: &Secrets) -> Result<(), Error> {
+        let envelope = match self.passphrase {
+            None => serde_json::to_vec(&Envelope::Plain { secrets: secrets.clone() }),
+
+            Some(ref passphrase) => {
+                let mut salt = [0; SALT_LEN];
+                let mut nonce = [0; NONCE_LEN];
+                rand::thread_rng().fill_bytes(&mut salt);
+                rand::thread_rng().fill_bytes(&mut nonce);
+
+                let plain = serde_json::to_vec(secrets).map_err(std::io::Error::other)?;
+                let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt).into());
+                let data = cipher.encrypt(Nonce::from_slice(&nonce), plain.as_ref())
+                    .map_err(|_| Error::Credentials("could not encrypt the credentials".to_owned()))?;
+
+                serde_json::to_vec(&Envelope::Encrypted {
+                    salt: BASE64.encode(salt),
+                    nonce: BASE64.encode(nonce),
+                    data: BASE64.encode(data),
+                })
+            },
+        };
+
+        let envelope = envelope.map_err(std::io::Error::other)?;
+
+        // write next to the real file and swap it in, so a crash never leaves half a file behind
+        let temp = self.path.with_extension("json.tmp");
+        let mut file = create_private(&temp)?;
+        file.write_all(&envelope)?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.path)?;
+
+        Ok(())
+    }
+
+    /// Overwrites and removes the stored secrets.
+    pub fn wipe(&mut self) -> Result<(), Error> {
+        self.passphrase = None;
+        wipe_file(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::record::test::TempDir;
+
+    fn secrets() -> Secrets {
+        Secrets {
+            credentials: Some(Credentials::with_password("rick", "never gonna give you up")),
+            refresh_token: Some("refresh".to_owned()),
+        }
+    }
+
+    fn assert_same(loaded: Secrets, saved: Secrets) {
+        assert_eq!(loaded.credentials, saved.credentials);
+        assert_eq!(loaded.refresh_token, saved.refresh_token);
+    }
+
+    fn assert_fails(result: Result<Secrets, Error>, reason: &str) {
+        match result {
+            Err(Error::Credentials(message)) => assert!(message.contains(reason), "{}", message),
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("loaded the secrets"),
+        }
+    }
+
+    fn encrypted(dir: &TempDir, passphrase: &str) -> CredentialStore {
+        let mut store = CredentialStore::at(&dir.0.join(CREDENTIALS_FILE));
+        store.set_passphrase(passphrase.to_owned());
+        store
+    }
+
+    #[test]
+    fn plain_secrets_round_trip() {
+        let dir = TempDir::new();
+        let store = CredentialStore::at(&dir.0.join(CREDENTIALS_FILE));
+        store.save(&secrets()).unwrap();
+
+        let data = fs::read_to_string(store.path()).unwrap();
+        assert!(data.contains(r#""format":"plain""#));
+
+        let mut store = CredentialStore::at(store.path());
+        assert_same(store.load().unwrap(), secrets());
+        assert!(!store.is_encrypted());
+    }
+
+    #[test]
+    fn encrypted_secrets_round_trip() {
+        let dir = TempDir::new();
+        encrypted(&dir, "correct horse").save(&secrets()).unwrap();
+
+        let data = fs::read_to_string(dir.0.join(CREDENTIALS_FILE)).unwrap();
+        assert!(data.contains(r#""format":"encrypted""#));
+        assert!(!data.contains("refresh"));
+
+        let mut store = encrypted(&dir, "correct horse");
+        assert_same(store.load().unwrap(), secrets());
+        assert!(store.is_encrypted());
+    }
+
+    #[test]
+    fn a_wrong_passphrase_is_refused() {
+        let dir = TempDir::new();
+        encrypted(&dir, "correct horse").save(&secrets()).unwrap();
+
+        assert_fails(encrypted(&dir, "battery staple").load(), "wrong passphrase");
+    }
+
+    #[test]
+    fn tampered_secrets_are_refused() {
+        let dir = TempDir::new();
+        let store = encrypted(&dir, "correct horse");
+        store.save(&secrets()).unwrap();
+
+        let envelope = serde_json::from_slice(&fs::read(store.path()).unwrap()).unwrap();
+        let Envelope::Encrypted { salt, nonce, data } = envelope else {
+            panic!("the secrets were not encrypted");
+        };
+        let mut data = BASE64.decode(data).unwrap();
+        data[0] ^= 1;
+        let tampered = Envelope::Encrypted { salt, nonce, data: BASE64.encode(data) };
+        fs::write(store.path(), serde_json::to_vec(&tampered).unwrap()).unwrap();
+
+        assert_fails(encrypted(&dir, "correct horse").load(), "wrong passphrase");
+
+        fs::write(store.path(), "{ not json").unwrap();
+        assert_fails(encrypted(&dir, "correct horse").load(), "corrupted");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_the_user_can_read_the_secrets() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let config = private_dir(dir.0.join("config")).unwrap();
+        let store = CredentialStore::at(&config.join(CREDENTIALS_FILE));
+        store.save(&secrets()).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir.0.join("config")), 0o700);
+        assert_eq!(mode(store.path()), 0o600);
+    }
+}